@@ -20,7 +20,7 @@ fn main() {
         .unwrap();
         let build_time = String::from_utf8(
             if cfg!(windows) {
-                Command::new("cmd").args(&["/C", "Get-Date"]).output()
+                Command::new("cmd").args(["/C", "Get-Date"]).output()
             } else {
                 Command::new("sh").arg("-c").arg("date -u").output()
             }
@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_to_string, OpenOptions};
+use std::io::prelude::*;
+use std::path::Path;
+
+use serde::Serialize;
+use toml::Value;
+
+use super::{
+    errors::{Error, Result},
+    models::{expand, Group, Vars, CONFIG_EXT},
+};
+
+pub const ALL: &str = "all";
+pub const UNGROUPED: &str = "ungrouped";
+
+#[derive(Default, Debug)]
+pub struct Inventory {
+    pub groups: BTreeMap<String, Group>,
+    pub hosts: BTreeMap<String, Vars>,
+}
+
+impl Inventory {
+    fn group(&mut self, name: &str) -> &mut Group {
+        self.groups
+            .entry(name.to_string())
+            .or_insert_with(|| Group {
+                hosts: Vec::new(),
+                children: Vec::new(),
+                vars: Vars::new(),
+            })
+    }
+
+    fn host(&mut self, group: &str, name: &str, vars: Vars) {
+        let it = self.group(group);
+        if !it.hosts.iter().any(|x| x == name) {
+            it.hosts.push(name.to_string());
+        }
+        self.hosts.entry(name.to_string()).or_default().extend(vars);
+    }
+
+    fn child(&mut self, group: &str, name: &str) {
+        self.group(name);
+        let it = self.group(group);
+        if !it.children.iter().any(|x| x == name) {
+            it.children.push(name.to_string());
+        }
+    }
+
+    pub fn parse_ini(buf: &str) -> Result<Self> {
+        let mut it = Self::default();
+        let mut section = (UNGROUPED.to_string(), None::<String>);
+        for (no, line) in buf.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = &line[1..line.len() - 1];
+                section = match name.find(':') {
+                    Some(i) => (name[..i].to_string(), Some(name[i + 1..].to_string())),
+                    None => (name.to_string(), None),
+                };
+                it.group(&section.0);
+                continue;
+            }
+            let (group, kind) = (&section.0, section.1.as_deref());
+            match kind {
+                None => {
+                    let mut items = split(line).into_iter();
+                    let host = items.next().unwrap_or_default();
+                    let vars = items
+                        .map(|x| parse_pair(&x, no))
+                        .collect::<Result<Vec<_>>>()?;
+                    it.host(group, &host, vars.into_iter().collect());
+                }
+                Some("vars") => {
+                    let (k, v) = parse_pair(line, no)?;
+                    it.group(group).vars.insert(k, v);
+                }
+                Some("children") => {
+                    it.child(group, line);
+                }
+                Some(v) => {
+                    return Err(Error::Custom(format!(
+                        "unknown section type {} at line {}",
+                        v,
+                        no + 1
+                    )))
+                }
+            }
+        }
+        Ok(it)
+    }
+
+    pub fn parse_yaml(buf: &str) -> Result<Self> {
+        #[derive(Deserialize, Default)]
+        struct Node {
+            #[serde(default)]
+            hosts: BTreeMap<String, Option<Vars>>,
+            #[serde(default)]
+            vars: Vars,
+            #[serde(default)]
+            children: BTreeMap<String, Option<Node>>,
+        }
+        fn walk(it: &mut Inventory, name: &str, node: Node) {
+            it.group(name).vars.extend(node.vars);
+            for (host, vars) in node.hosts {
+                it.host(name, &host, vars.unwrap_or_default());
+            }
+            for (child, node) in node.children {
+                it.child(name, &child);
+                walk(it, &child, node.unwrap_or_default());
+            }
+        }
+
+        let root: BTreeMap<String, Option<Node>> = serde_yaml::from_str(buf)?;
+        let mut it = Self::default();
+        for (name, node) in root {
+            walk(&mut it, &name, node.unwrap_or_default());
+        }
+        Ok(it)
+    }
+
+    // converts the connection vars and writes the axis directory layout
+    pub fn save(mut self, root: &Path) -> Result<()> {
+        for group in self.groups.values_mut() {
+            rename_vars(&mut group.vars);
+        }
+        for vars in self.hosts.values_mut() {
+            rename_vars(vars);
+        }
+
+        if let Some(all) = self.groups.remove(ALL) {
+            if !all.vars.is_empty() {
+                write(&root.join(format!("{}.{}", ALL, CONFIG_EXT)), &all.vars)?;
+            }
+            for host in all.hosts.iter() {
+                if !self.groups.values().any(|x| x.hosts.contains(host)) {
+                    self.host(UNGROUPED, host, Vars::new());
+                }
+            }
+        }
+        for (name, group) in self.groups.iter() {
+            if name == UNGROUPED && group.hosts.is_empty() {
+                continue;
+            }
+            write(
+                &root.join("groups").join(format!("{}.{}", name, CONFIG_EXT)),
+                group,
+            )?;
+        }
+        for (name, vars) in self.hosts.iter() {
+            if vars.is_empty() {
+                continue;
+            }
+            for name in expand(name)? {
+                write(
+                    &root.join("hosts").join(format!("{}.{}", name, CONFIG_EXT)),
+                    vars,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn import(src: &Path, dest: &Path) -> Result<()> {
+    info!(
+        "import ansible inventory {} to {}",
+        src.display(),
+        dest.display()
+    );
+    let buf = read_to_string(src)?;
+    let it = match src.extension().and_then(|x| x.to_str()) {
+        Some("yaml") | Some("yml") => Inventory::parse_yaml(&buf)?,
+        _ => Inventory::parse_ini(&buf)?,
+    };
+    it.save(dest)
+}
+
+fn rename_vars(vars: &mut Vars) {
+    for (from, to) in &[
+        ("ansible_host", "ssh.host"),
+        ("ansible_user", "ssh.user"),
+        ("ansible_port", "ssh.port"),
+        ("ansible_ssh_private_key_file", "ssh.key-file"),
+        ("ansible_shell_executable", "ssh.shell"),
+    ] {
+        if let Some(v) = vars.remove(*from) {
+            vars.insert(to.to_string(), v);
+        }
+    }
+}
+
+fn parse_pair(item: &str, no: usize) -> Result<(String, Value)> {
+    match item.find('=') {
+        Some(i) => Ok((
+            item[..i].trim().to_string(),
+            parse_value(item[i + 1..].trim()),
+        )),
+        None => Err(Error::Custom(format!(
+            "bad variable {} at line {}",
+            item,
+            no + 1
+        ))),
+    }
+}
+
+// splits a host line by whitespaces, quoted values are kept together
+fn split(line: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut cur = String::new();
+    let mut quote = None;
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if q == c => {
+                quote = None;
+                cur.push(c);
+            }
+            (Some(_), c) => cur.push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                cur.push(c);
+            }
+            (None, c) if c.is_whitespace() => {
+                if !cur.is_empty() {
+                    items.push(cur.clone());
+                    cur.clear();
+                }
+            }
+            (None, c) => cur.push(c),
+        }
+    }
+    if !cur.is_empty() {
+        items.push(cur);
+    }
+    items
+}
+
+fn parse_value(v: &str) -> Value {
+    if let Ok(v) = v.parse::<i64>() {
+        return Value::Integer(v);
+    }
+    if v.chars()
+        .all(|c| c.is_ascii_digit() || c == '.' || c == '-')
+    {
+        if let Ok(v) = v.parse::<f64>() {
+            return Value::Float(v);
+        }
+    }
+    match v {
+        "true" | "True" | "yes" => return Value::Boolean(true),
+        "false" | "False" | "no" => return Value::Boolean(false),
+        _ => {}
+    }
+    let v = if v.len() >= 2
+        && ((v.starts_with('"') && v.ends_with('"')) || (v.starts_with('\'') && v.ends_with('\'')))
+    {
+        &v[1..v.len() - 1]
+    } else {
+        v
+    };
+    Value::String(v.to_string())
+}
+
+// existing files are never overwritten
+fn write<T: Serialize>(file: &Path, it: &T) -> Result<()> {
+    if let Some(it) = file.parent() {
+        if !it.exists() {
+            create_dir_all(it)?;
+        }
+    }
+    info!("generate {}", file.display());
+    let buf = toml::to_string_pretty(&Value::try_from(it)?)?;
+    let mut file = OpenOptions::new().create_new(true).write(true).open(file)?;
+    file.write_all(buf.as_bytes())?;
+    Ok(())
+}
@@ -1,13 +1,16 @@
-use std::ops::Deref;
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::path::Path;
+use std::sync::Arc;
 
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
+use toml::Value;
 
 use super::{
+    ansible,
+    cache::Cache,
     env,
     errors::{Error, Result},
-    models::Job,
+    models::{self, Job, Vars},
+    pattern, sensitive, transport, vault,
 };
 
 pub fn run() -> Result<()> {
@@ -33,8 +36,232 @@ pub fn run() -> Result<()> {
                 .help("Inventory")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("vault-password-file")
+                .long("vault-password-file")
+                .value_name("FILE")
+                .help("Vault password file")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keep-rendered")
+                .long("keep-rendered")
+                .help("Keep rendered templates in tmp/cache for debugging"),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .short("l")
+                .long("limit")
+                .value_name("PATTERN")
+                .help("Limit selected hosts to an additional pattern")
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("inventory")
+                .about("Manage inventories")
+                .subcommand(
+                    SubCommand::with_name("import")
+                        .about("Import an inventory from another tool")
+                        .arg(
+                            Arg::with_name("from")
+                                .long("from")
+                                .value_name("TOOL")
+                                .help("Source format")
+                                .possible_values(&["ansible"])
+                                .default_value("ansible")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("source")
+                                .value_name("SOURCE")
+                                .help("Source inventory file(ini or yaml)")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("to")
+                                .long("to")
+                                .value_name("INVENTORY")
+                                .help("Target inventory directory")
+                                .required(true)
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("vault")
+                .about("Encrypt secrets in inventory files")
+                .subcommand(
+                    SubCommand::with_name("encrypt")
+                        .about("Encrypt a file or a value")
+                        .arg(
+                            Arg::with_name("string")
+                                .long("string")
+                                .value_name("VALUE")
+                                .help("A single value instead of a file")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("file")
+                                .value_name("FILE")
+                                .help("File")
+                                .required_unless("string"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("decrypt")
+                        .about("Decrypt a file or a value")
+                        .arg(
+                            Arg::with_name("string")
+                                .long("string")
+                                .value_name("VALUE")
+                                .help("A single value instead of a file")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("file")
+                                .value_name("FILE")
+                                .help("File")
+                                .required_unless("string"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("edit")
+                        .about("Edit an encrypted file by $EDITOR")
+                        .arg(
+                            Arg::with_name("file")
+                                .value_name("FILE")
+                                .help("File")
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("known-hosts")
+                .about("Manage the known_hosts file of an inventory")
+                .subcommand(
+                    SubCommand::with_name("scan")
+                        .about("Add the host keys of all ssh hosts by ssh-keyscan")
+                        .arg(
+                            Arg::with_name("inventory")
+                                .short("i")
+                                .long("inventory")
+                                .value_name("INVENTORY")
+                                .help("Inventory")
+                                .required(true)
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("vars")
+                .about("Show the resolved vars of a host")
+                .arg(
+                    Arg::with_name("job")
+                        .short("j")
+                        .long("job")
+                        .value_name("JOB")
+                        .help("Job")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("inventory")
+                        .short("i")
+                        .long("inventory")
+                        .value_name("INVENTORY")
+                        .help("Inventory")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .value_name("HOST")
+                        .help("Host")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("explain")
+                        .long("explain")
+                        .help("Show which layer set each var and which layers it overrode"),
+                )
+                .arg(
+                    Arg::with_name("show-sensitive")
+                        .long("show-sensitive")
+                        .help("Show sensitive values instead of masking them"),
+                ),
+        )
         .get_matches();
 
+    if let Some(file) = matches.value_of("vault-password-file") {
+        vault::set_password_file(file)?;
+    }
+
+    if let Some(matches) = matches.subcommand_matches("vault") {
+        return match matches.subcommand() {
+            ("encrypt", Some(matches)) => match matches.value_of("string") {
+                Some(v) => {
+                    println!("{:?}", vault::encrypt(v.as_bytes())?);
+                    Ok(())
+                }
+                None => vault::encrypt_file(matches.value_of("file").unwrap_or_default()),
+            },
+            ("decrypt", Some(matches)) => match matches.value_of("string") {
+                Some(v) => {
+                    println!("{}", vault::decrypt_str(v)?);
+                    Ok(())
+                }
+                None => vault::decrypt_file(matches.value_of("file").unwrap_or_default()),
+            },
+            ("edit", Some(matches)) => {
+                vault::edit_file(matches.value_of("file").unwrap_or_default())
+            }
+            _ => Err(Error::Custom("please give a vault command".to_string())),
+        };
+    }
+    if let Some(matches) = matches.subcommand_matches("inventory") {
+        if let Some(matches) = matches.subcommand_matches("import") {
+            if let (Some(src), Some(dest)) = (matches.value_of("source"), matches.value_of("to")) {
+                return ansible::import(Path::new(src), Path::new(dest));
+            }
+        }
+        return Err(Error::Custom("please give a inventory command".to_string()));
+    }
+    if let Some(matches) = matches.subcommand_matches("known-hosts") {
+        if let Some(matches) = matches.subcommand_matches("scan") {
+            if let Some(inventory) = matches.value_of("inventory") {
+                let skipped = transport::scan(inventory)?;
+                if !skipped.is_empty() {
+                    println!("not scanned:");
+                    for it in skipped {
+                        println!("    {}", it);
+                    }
+                }
+                return Ok(());
+            }
+        }
+        return Err(Error::Custom(
+            "please give a known-hosts command".to_string(),
+        ));
+    }
+    if let Some(matches) = matches.subcommand_matches("vars") {
+        if let (Some(job), Some(inventory), Some(host)) = (
+            matches.value_of("job"),
+            matches.value_of("inventory"),
+            matches.value_of("host"),
+        ) {
+            return vars(
+                job,
+                inventory,
+                host,
+                matches.is_present("explain"),
+                matches.is_present("show-sensitive"),
+            );
+        }
+    }
+
     let job = matches
         .value_of("job")
         .ok_or_else(|| Error::Custom("please give a job name".to_string()))?;
@@ -42,49 +269,87 @@ pub fn run() -> Result<()> {
     let inventory = matches
         .value_of("inventory")
         .ok_or_else(|| Error::Custom("please give a inventory name".to_string()))?;
-    let reason = Arc::new(Mutex::new(None::<Error>));
+    let _cache = Cache::new(matches.is_present("keep-rendered"))?;
 
-    let excutors = Job::load(job, inventory)?;
-    for (hosts, tasks) in excutors {
-        {
-            let reason = reason.lock();
-            if let Ok(ref reason) = reason {
-                if let Some(ref e) = reason.deref() {
-                    return Err(Error::Custom(e.to_string()));
-                }
-            }
-        }
-        let mut children = vec![];
-
-        for (host, vars) in hosts {
-            let host = host.clone();
-            let vars = vars.clone();
-            let tasks = tasks.clone();
-            let reason = reason.clone();
-            let inventory = inventory.to_string();
-            children.push(
-                thread::Builder::new()
-                    .name(format!("{}-{}-{}", host, job, inventory))
-                    .spawn(move || {
-                        let reason = reason.clone();
-                        for task in tasks {
-                            info!("run {} on {}", task, host);
-                            if let Err(e) = task.run(&inventory, &host, &vars) {
-                                if let Ok(mut reason) = reason.lock() {
-                                    *reason = Some(e);
-                                }
-                                return;
-                            }
-                        }
-                    })?,
-            );
-        }
-        for it in children {
-            info!("waiting for thread finished...");
-            let _ = it.join();
+    let mut excutors = Job::load(job, inventory)?;
+    if let Some(limit) = matches.value_of("limit") {
+        let limit = pattern::select(limit, inventory, Vars::new())?;
+        for (hosts, _) in excutors.iter_mut() {
+            hosts.retain(|(host, _)| limit.iter().any(|(x, _)| x == host));
         }
     }
+    Job::run(job, inventory, excutors, Arc::new(transport::open))?;
 
     info!("Done.");
     Ok(())
 }
+
+fn vars(job: &str, inventory: &str, host: &str, explain: bool, reveal: bool) -> Result<()> {
+    let mut found = false;
+    for (task, hosts, _) in Job::trace(job, inventory)? {
+        for (_, layers) in hosts.iter().filter(|(x, _)| x == host) {
+            found = true;
+            println!("# task {}: {}", task, host);
+            let raw = models::flatten(layers);
+            let mut vars = raw.clone();
+            models::resolve(&mut vars)?;
+            let show = |v: &Value| {
+                if reveal {
+                    v.to_string()
+                } else {
+                    sensitive::mask(&v.to_string())
+                }
+            };
+            let redacted = if reveal {
+                vars.clone()
+            } else {
+                sensitive::register(&vars, &vars);
+                sensitive::register(&raw, &vars);
+                for (_, it) in layers.iter() {
+                    sensitive::register(it, &vars);
+                }
+                sensitive::redact(&vars)
+            };
+            if !explain {
+                println!("{}", toml::to_string_pretty(&redacted)?);
+                continue;
+            }
+            for (path, mut items) in models::sources(layers) {
+                let val = match lookup(&vars, &path) {
+                    Some(v) => v,
+                    None => continue,
+                };
+                println!(
+                    "{} = {}",
+                    path.iter()
+                        .map(|x| format!("{:?}", x))
+                        .collect::<Vec<_>>()
+                        .join("."),
+                    lookup(&redacted, &path).map(&show).unwrap_or_default()
+                );
+                if let Some(v) = lookup(&raw, &path).filter(|v| *v != val) {
+                    println!("    rendered from {}", show(v));
+                }
+                if let Some((name, _)) = items.pop() {
+                    println!("    set by {}", name);
+                }
+                for (name, val) in items.iter().rev() {
+                    println!("    overrides {} = {}", name, show(val));
+                }
+            }
+            println!();
+        }
+    }
+    if !found {
+        return Err(Error::Custom(format!(
+            "host {} isn't used by {}@{}",
+            host, job, inventory
+        )));
+    }
+    Ok(())
+}
+
+fn lookup<'a>(vars: &'a Vars, path: &[String]) -> Option<&'a Value> {
+    let (first, rest) = path.split_first()?;
+    rest.iter().try_fold(vars.get(first)?, |v, k| v.get(k))
+}
@@ -0,0 +1,182 @@
+use std::fs::{create_dir_all, read_dir, remove_dir_all};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Once, RwLock};
+
+use uuid::Uuid;
+
+use super::{
+    errors::{Error, Result},
+    transport, ROOT,
+};
+
+lazy_static! {
+    static ref CURRENT: RwLock<Option<Current>> = RwLock::new(None);
+}
+
+struct Current {
+    root: PathBuf,
+    sockets: Option<PathBuf>,
+    keep: bool,
+}
+
+static CTRL_C: Once = Once::new();
+
+const SECRETS: &str = ".secrets";
+
+// the size of sun_path on macos (linux has 108)
+const SUN_PATH_LEN: usize = 104;
+// "/" + %C (40 chars) + the temporary suffix (".XXXXXXXXXXXXXXXX") added by ssh
+const SOCKET_LEN: usize = 1 + 40 + 17;
+
+// a private directory for the rendered templates of one run and another one for
+// the ssh control sockets, they are removed when the guard is dropped (on success
+// or error) or on Ctrl-C
+pub struct Cache {
+    root: PathBuf,
+}
+
+impl Cache {
+    pub fn new(keep: bool) -> Result<Self> {
+        let id = Uuid::new_v4().to_string();
+        let root = ROOT.join("cache").join(&id);
+        Self::create(&root)?;
+        debug!("render templates into {}", root.display());
+        // not $TMPDIR, it's too long on macos for the socket paths
+        #[cfg(not(windows))]
+        let sockets = {
+            let it = Path::new("/tmp").join(format!("axis-{}", &id[..8]));
+            if it.display().to_string().len() + SOCKET_LEN < SUN_PATH_LEN {
+                Self::create(&it)?;
+                Some(it)
+            } else {
+                warn!(
+                    "{} is too long for ssh sockets, multiplexing is off",
+                    it.display()
+                );
+                None
+            }
+        };
+        #[cfg(windows)]
+        let sockets = None;
+        {
+            let mut it = CURRENT.write().map_err(|e| Error::Custom(e.to_string()))?;
+            *it = Some(Current {
+                root: root.clone(),
+                sockets,
+                keep,
+            });
+        }
+        CTRL_C.call_once(|| {
+            if let Err(e) = ctrlc::set_handler(|| {
+                Self::clean();
+                process::exit(130);
+            }) {
+                warn!("can't set the Ctrl-C handler: {}", e);
+            }
+        });
+        Ok(Self { root })
+    }
+
+    fn create(root: &Path) -> Result<()> {
+        #[cfg(not(windows))]
+        {
+            use std::fs::DirBuilder;
+            use std::os::unix::fs::DirBuilderExt;
+            if let Some(it) = root.parent() {
+                create_dir_all(it)?;
+            }
+            DirBuilder::new().mode(0o700).create(root)?;
+        }
+        #[cfg(windows)]
+        create_dir_all(root)?;
+        Ok(())
+    }
+
+    pub fn root() -> Result<PathBuf> {
+        let it = CURRENT.read().map_err(|e| Error::Custom(e.to_string()))?;
+        match *it {
+            Some(ref it) => Ok(it.root.clone()),
+            None => Err(Error::Custom("the render cache isn't opened".to_string())),
+        }
+    }
+
+    // a private directory for secrets (such as ssh passwords) which is removed even with keep
+    pub fn secrets() -> Result<PathBuf> {
+        let it = Self::root()?.join(SECRETS);
+        if !it.exists() {
+            Self::create(&it)?;
+        }
+        Ok(it)
+    }
+
+    // None when no cache is opened or ssh multiplexing isn't supported
+    pub fn sockets() -> Option<PathBuf> {
+        match CURRENT.read() {
+            Ok(it) => it.as_ref().and_then(|x| x.sockets.clone()),
+            Err(_) => None,
+        }
+    }
+
+    fn clean() {
+        if let Ok(mut it) = CURRENT.write() {
+            if let Some(Current {
+                root,
+                sockets,
+                keep,
+            }) = it.take()
+            {
+                if let Some(sockets) = sockets {
+                    transport::disconnect(&sockets);
+                    if let Err(e) = remove_dir_all(&sockets) {
+                        error!("can't remove {}: {}", sockets.display(), e);
+                    }
+                }
+                let secrets = root.join(SECRETS);
+                if secrets.exists() {
+                    if let Err(e) = remove_dir_all(&secrets) {
+                        error!("can't remove {}: {}", secrets.display(), e);
+                    }
+                }
+                if keep {
+                    info!("keep rendered templates in {}", root.display());
+                } else if root.exists() {
+                    debug!("remove {}", root.display());
+                    if let Err(e) = writable(&root).and_then(|_| Ok(remove_dir_all(&root)?)) {
+                        error!("can't remove {}: {}", root.display(), e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Cache {
+    fn drop(&mut self) {
+        let current = match CURRENT.read() {
+            Ok(it) => it.as_ref().map(|x| x.root == self.root).unwrap_or(false),
+            Err(_) => false,
+        };
+        if current {
+            Self::clean();
+        }
+    }
+}
+
+// staged directories keep the modes of their sources, a read-only one couldn't be emptied
+fn writable(dir: &Path) -> Result<()> {
+    #[cfg(not(windows))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut it = dir.metadata()?.permissions();
+        it.set_mode(it.mode() | 0o700);
+        std::fs::set_permissions(dir, it)?;
+    }
+    for it in read_dir(dir)? {
+        let it = it?.path();
+        if it.symlink_metadata()?.is_dir() {
+            writable(&it)?;
+        }
+    }
+    Ok(())
+}
@@ -9,10 +9,15 @@ pub enum Error {
     StdIo(std::io::Error),
     StdNetAddrParse(std::net::AddrParseError),
 
-    HandlebarsRender(handlebars::RenderError),
-    HandlebarsTemplate(handlebars::TemplateError),
-    HandlebarsTemplateRender(handlebars::TemplateRenderError),
+    HandlebarsRender(Box<handlebars::RenderError>),
+    HandlebarsTemplate(Box<handlebars::TemplateError>),
+    HandlebarsTemplateRender(Box<handlebars::TemplateRenderError>),
+    Base64Decode(base64::DecodeError),
     TomlDe(toml::de::Error),
+    TomlSer(toml::ser::Error),
+    SerdeJson(serde_json::Error),
+    SerdeYaml(serde_yaml::Error),
+    Regex(regex::Error),
 
     Custom(String),
 }
@@ -27,7 +32,12 @@ impl fmt::Display for Error {
             Self::HandlebarsRender(v) => v.fmt(f),
             Self::HandlebarsTemplate(v) => v.fmt(f),
             Self::HandlebarsTemplateRender(v) => v.fmt(f),
+            Self::Base64Decode(v) => v.fmt(f),
             Self::TomlDe(v) => v.fmt(f),
+            Self::TomlSer(v) => v.fmt(f),
+            Self::SerdeJson(v) => v.fmt(f),
+            Self::SerdeYaml(v) => v.fmt(f),
+            Self::Regex(v) => v.fmt(f),
 
             Self::Custom(v) => v.fmt(f),
         }
@@ -54,19 +64,25 @@ impl From<std::net::AddrParseError> for Error {
 
 impl From<handlebars::RenderError> for Error {
     fn from(err: handlebars::RenderError) -> Self {
-        Self::HandlebarsRender(err)
+        Self::HandlebarsRender(Box::new(err))
     }
 }
 
 impl From<handlebars::TemplateError> for Error {
     fn from(err: handlebars::TemplateError) -> Self {
-        Self::HandlebarsTemplate(err)
+        Self::HandlebarsTemplate(Box::new(err))
     }
 }
 
 impl From<handlebars::TemplateRenderError> for Error {
     fn from(err: handlebars::TemplateRenderError) -> Self {
-        Self::HandlebarsTemplateRender(err)
+        Self::HandlebarsTemplateRender(Box::new(err))
+    }
+}
+
+impl From<base64::DecodeError> for Error {
+    fn from(err: base64::DecodeError) -> Self {
+        Self::Base64Decode(err)
     }
 }
 
@@ -75,3 +91,27 @@ impl From<toml::de::Error> for Error {
         Self::TomlDe(err)
     }
 }
+
+impl From<toml::ser::Error> for Error {
+    fn from(err: toml::ser::Error) -> Self {
+        Self::TomlSer(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::SerdeJson(err)
+    }
+}
+
+impl From<serde_yaml::Error> for Error {
+    fn from(err: serde_yaml::Error) -> Self {
+        Self::SerdeYaml(err)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(err: regex::Error) -> Self {
+        Self::Regex(err)
+    }
+}
@@ -5,16 +5,30 @@ extern crate log;
 #[macro_use]
 extern crate lazy_static;
 
+extern crate argon2;
+extern crate base64;
+extern crate chacha20poly1305;
 extern crate chrono;
 extern crate clap;
+extern crate ctrlc;
 extern crate rand;
+extern crate regex;
+extern crate rpassword;
+extern crate serde_json;
+extern crate serde_yaml;
 extern crate toml;
 extern crate uuid;
 
+pub mod ansible;
 pub mod app;
+pub mod cache;
 pub mod env;
 pub mod errors;
 pub mod models;
+pub mod pattern;
+pub mod sensitive;
+pub mod transport;
+pub mod vault;
 
 use std::path::{Path, PathBuf};
 
@@ -4,6 +4,6 @@ extern crate log;
 fn main() {
     env_logger::init();
     if let Err(e) = axis::app::run() {
-        error!("{}", e);
+        error!("{}", axis::sensitive::mask(&e.to_string()));
     }
 }
@@ -1,74 +1,279 @@
 use std::collections::BTreeMap;
 use std::fmt;
-use std::fs::{create_dir_all, File, OpenOptions};
-use std::io::{prelude::*, BufReader, BufWriter};
+use std::fs::{copy, create_dir_all, read_dir, read_link, set_permissions, File, OpenOptions};
+use std::io::{prelude::*, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{Command as ShellCommand, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 use chrono::Utc;
-use handlebars::Handlebars;
+use handlebars::{no_escape, Handlebars};
 use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use serde::de::DeserializeOwned;
+use regex::Regex;
+use serde::{de::DeserializeOwned, Serialize};
 use toml::Value;
 use uuid::Uuid;
 
-use super::errors::{Error, Result};
+use super::{
+    cache::Cache,
+    errors::{Error, Result},
+    pattern, sensitive,
+    transport::{self, Opener, Transport},
+    vault, ROOT,
+};
 
 pub const CONFIG_EXT: &str = "toml";
+pub const CONFIG_EXTS: &[&str] = &[CONFIG_EXT, "yaml", "yml", "json"];
 pub const TEMPLATE_EXT: &str = "hbs";
 pub const JOBS: &str = "jobs";
 
+pub const REPLACE: &str = "__replace__";
+
 pub type Vars = BTreeMap<String, Value>;
 pub type Excutor = (Vec<Host>, Vec<Command>);
 pub type Host = (String, Vars);
+// a named source of vars, such as "all", "task <name>" or "group <name>"
+pub type Layer = (String, Vars);
+// a host with all the vars layers it is built from, in order of precedence
+pub type Trace = (String, Vec<Layer>);
+pub type Traced = (String, Vec<Trace>, Vec<Command>);
+
+pub const BUILT_IN: &str = "built-in";
 
+// loads <name>.toml and then every file of the <name>/ directory in lexical order
 macro_rules! load_vars {
     ($i:expr, $n:expr, $v:expr) => {
-        let file = $i.join(&format!("{}.{}", $n, CONFIG_EXT));
-        if file.exists() {
+        if let Some(file) = find_config(&$i, $n)? {
             debug!("load vars from {}", file.display());
             let cur: Vars = parse(file)?;
-            $v.extend(cur);
+            merge(&mut $v, cur);
+        }
+        let root = $i.join($n);
+        if root.is_dir() {
+            let mut items = Vec::new();
+            for it in read_dir(&root)? {
+                let it = it?.path();
+                if it.is_file() && matches!(file_ext(&it), Some(x) if CONFIG_EXTS.contains(&x)) {
+                    items.push(it);
+                }
+            }
+            items.sort();
+            for file in items {
+                debug!("load vars from {}", file.display());
+                let cur: Vars = parse(file)?;
+                merge(&mut $v, cur);
+            }
         }
     };
 }
 
+lazy_static! {
+    static ref INVENTORIES: Mutex<BTreeMap<String, Arc<Inventory>>> = Mutex::new(BTreeMap::new());
+}
+
+// an inventory given as a single file (or an executable) instead of a directory
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Inventory {
+    #[serde(default)]
+    pub vars: Vars,
+    #[serde(default)]
+    pub groups: BTreeMap<String, Group>,
+    #[serde(default)]
+    pub hosts: BTreeMap<String, Vars>,
+}
+
+impl Inventory {
+    // the file is loaded (and the executable runs) only once for each run
+    pub fn open(inventory: &str) -> Result<Option<Arc<Self>>> {
+        let file = Path::new(inventory);
+        if !file.is_file() {
+            return Ok(None);
+        }
+        let mut cache = INVENTORIES
+            .lock()
+            .map_err(|e| Error::Custom(e.to_string()))?;
+        if let Some(it) = cache.get(inventory) {
+            return Ok(Some(it.clone()));
+        }
+        let it: Arc<Self> = Arc::new(if is_executable(file) {
+            execute(file)?
+        } else {
+            parse(file)?
+        });
+        cache.insert(inventory.to_string(), it.clone());
+        Ok(Some(it))
+    }
+
+    // the vars of all.toml (or the vars table of a single-file inventory)
+    pub fn all_vars(inventory: &str) -> Result<Vars> {
+        let mut vars = Vars::new();
+        match Self::open(inventory)? {
+            Some(cur) => merge(&mut vars, cur.vars.clone()),
+            None => {
+                load_vars!(Path::new(inventory), "all", vars);
+            }
+        };
+        Ok(vars)
+    }
+
+    fn load_vars(inventory: &str, host: &str, vars: &mut Vars) -> Result<()> {
+        match Self::open(inventory)? {
+            Some(it) => {
+                if let Some(cur) = it.hosts.get(host) {
+                    merge(vars, cur.clone());
+                }
+            }
+            None => {
+                load_vars!(Path::new(inventory).join("hosts"), host, *vars);
+            }
+        };
+        Ok(())
+    }
+}
+
+// the output of an executable group, host vars are given by the hostVars table
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Generated {
+    #[serde(flatten)]
+    group: Group,
+    #[serde(default)]
+    host_vars: BTreeMap<String, Vars>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Group {
+    #[serde(default)]
     pub hosts: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<String>,
+    #[serde(default)]
     pub vars: Vars,
 }
 
 impl Group {
+    pub fn names(inventory: &str) -> Result<Vec<String>> {
+        if let Some(it) = Inventory::open(inventory)? {
+            return Ok(it.groups.keys().cloned().collect());
+        }
+        let mut items = Vec::new();
+        for it in read_dir(Path::new(inventory).join("groups"))? {
+            let it = it?.path();
+            let ext = file_ext(&it);
+            if matches!(ext, Some(x) if CONFIG_EXTS.contains(&x))
+                || (ext.is_none() && is_executable(&it))
+            {
+                if let Some(name) = it.file_stem().and_then(|x| x.to_str()) {
+                    items.push(name.to_string());
+                }
+            }
+        }
+        items.sort();
+        items.dedup();
+        Ok(items)
+    }
+
+    fn read(name: &str, inventory: &str) -> Result<Generated> {
+        if let Some(it) = Inventory::open(inventory)? {
+            let group =
+                it.groups.get(name).cloned().ok_or_else(|| {
+                    Error::Custom(format!("can't find group {}@{}", name, inventory))
+                })?;
+            return Ok(Generated {
+                group,
+                host_vars: BTreeMap::new(),
+            });
+        }
+        let root = Path::new(inventory).join("groups");
+        {
+            let file = root.join(name);
+            if is_executable(&file) {
+                return execute(&file);
+            }
+        }
+        let file = find_config(&root, name)?
+            .ok_or_else(|| Error::Custom(format!("can't find group {}@{}", name, inventory)))?;
+        Ok(Generated {
+            group: parse(file)?,
+            host_vars: BTreeMap::new(),
+        })
+    }
+
     pub fn load(name: &str, inventory: &str, parent: Vars) -> Result<Vec<Host>> {
+        Ok(flatten_all(Self::trace(
+            name,
+            inventory,
+            vec![("parent".to_string(), parent)],
+        )?))
+    }
+
+    pub fn trace(name: &str, inventory: &str, parent: Vec<Layer>) -> Result<Vec<Trace>> {
+        let mut items = Vec::new();
+        Self::walk(name, inventory, parent, &mut Vec::new(), &mut items)?;
+        Ok(items)
+    }
+
+    // children are resolved before the group's own hosts, so a host reached
+    // through several paths keeps the vars of the most specific group
+    fn walk(
+        name: &str,
+        inventory: &str,
+        parent: Vec<Layer>,
+        parents: &mut Vec<String>,
+        items: &mut Vec<Trace>,
+    ) -> Result<()> {
+        if parents.iter().any(|it| it == name) {
+            return Err(Error::Custom(format!(
+                "cycle detected in groups: {} -> {}",
+                parents.join(" -> "),
+                name
+            )));
+        }
         info!("load group {}@{}", name, inventory);
-        let group = {
-            let mut it: Self = parse(
-                Path::new(inventory)
-                    .join("groups")
-                    .join(name)
-                    .with_extension(CONFIG_EXT),
-            )?;
-            it.vars = {
-                let mut vars = Vars::new();
-                vars.extend(parent);
-                vars.extend(it.vars);
-                vars.insert("group.name".to_string(), Value::String(name.to_string()));
-                vars
-            };
-            it
+        let Generated {
+            group,
+            mut host_vars,
+        } = Self::read(name, inventory)?;
+        let layers = {
+            let mut items = parent;
+            items.push((format!("group {}", name), group.vars));
+            items.push(built_in(&[("group.name", name)]));
+            items
         };
 
-        let mut items = Vec::new();
-        for host in group.hosts.iter() {
-            let mut vars = Vars::new();
-            vars.extend(group.vars.clone());
-            load_vars!(Path::new(inventory).join("hosts"), host, vars);
-            vars.insert("hostname".to_string(), Value::String(host.clone()));
-            items.push((host.clone(), vars));
+        parents.push(name.to_string());
+        for child in group.children.iter() {
+            Self::walk(child, inventory, layers.clone(), parents, items)?;
         }
-        Ok(items)
+        parents.pop();
+
+        for host in group
+            .hosts
+            .iter()
+            .map(|x| expand(x))
+            .collect::<Result<Vec<_>>>()?
+            .concat()
+            .iter()
+        {
+            if items.iter().any(|(it, _)| it == host) {
+                continue;
+            }
+            let mut layers = layers.clone();
+            if let Some(it) = host_vars.remove(host) {
+                layers.push((format!("host {} of group {}", host, name), it));
+            }
+            {
+                let mut vars = Vars::new();
+                Inventory::load_vars(inventory, host, &mut vars)?;
+                layers.push((format!("host {}", host), vars));
+            }
+            layers.push(built_in(&[("hostname", host)]));
+            items.push((host.clone(), layers));
+        }
+        Ok(())
     }
 }
 
@@ -97,54 +302,109 @@ impl Job {
     }
 
     pub fn load(name: &str, inventory: &str) -> Result<Vec<Excutor>> {
+        let mut excutors = Vec::new();
+        for (_, hosts, commands) in Self::trace(name, inventory)? {
+            let mut hosts = flatten_all(hosts);
+            for (_, vars) in hosts.iter_mut() {
+                resolve(vars)?;
+                sensitive::register(vars, vars);
+            }
+            excutors.push((hosts, commands));
+        }
+        Ok(excutors)
+    }
+
+    pub fn trace(name: &str, inventory: &str) -> Result<Vec<Traced>> {
         info!("load job {}@{}", name, inventory);
-        let job = {
-            let mut it: Self = parse(&Path::new(JOBS).join(name).with_extension(CONFIG_EXT))?;
-            it.vars
-                .insert("job.name".to_string(), Value::String(name.to_string()));
-            it.vars.insert(
-                "inventory.name".to_string(),
-                Value::String(inventory.to_string()),
-            );
-            it.vars.insert(
-                "timestamp".to_string(),
-                Value::String(Utc::now().format("%y%m%d%H%M%S%3f").to_string()),
-            );
-            it.vars.insert(
-                "uuid".to_string(),
-                Value::String(Uuid::new_v4().to_string()),
-            );
-            it.vars.insert(
-                "axis.version".to_string(),
-                Value::String(super::env::VERSION.to_string()),
-            );
+        let job: Self = parse(
+            find_config(Path::new(JOBS), name)?
+                .ok_or_else(|| Error::Custom(format!("can't find job {}", name)))?,
+        )?;
+        let mut layers = vec![(format!("job {}", name), job.vars)];
+        {
+            let timestamp = Utc::now().format("%y%m%d%H%M%S%3f").to_string();
+            let uuid = Uuid::new_v4().to_string();
+            let mut it = built_in(&[
+                ("job.name", name),
+                ("inventory.name", inventory),
+                ("timestamp", &timestamp),
+                ("uuid", &uuid),
+                ("axis.version", super::env::VERSION),
+            ]);
             if let Ok(v) = Self::git_version() {
-                it.vars.insert("git.version".to_string(), Value::String(v));
+                it.1.insert("git.version".to_string(), Value::String(v));
             }
-            load_vars!(Path::new(inventory), "all", it.vars);
-            {
-                let mut rng = thread_rng();
-                let random: String = std::iter::repeat(())
-                    .map(|()| rng.sample(Alphanumeric))
-                    .take(32)
-                    .collect();
-                it.vars.insert("random".to_string(), Value::String(random));
-            }
-            it
-        };
+            layers.push(it);
+        }
+        layers.push(("all".to_string(), Inventory::all_vars(inventory)?));
+        {
+            let mut rng = thread_rng();
+            let random: String = std::iter::repeat(())
+                .map(|()| rng.sample(Alphanumeric))
+                .take(32)
+                .collect();
+            layers.push(built_in(&[("random", &random)]));
+        }
+
         let mut excutors = Vec::new();
         for task in job.tasks.iter() {
             info!("load task {}@{}", task.name, inventory);
             for group in task.groups.iter() {
-                let mut vars = Vars::new();
-                vars.extend(job.vars.clone());
-                vars.extend(task.vars.clone());
-                let hosts = Group::load(group, inventory, vars)?;
-                excutors.push((hosts, task.commands.clone()));
+                let mut layers = layers.clone();
+                layers.push((format!("task {}", task.name), task.vars.clone()));
+                let hosts = pattern::trace(group, inventory, layers)?;
+                let commands = task
+                    .commands
+                    .iter()
+                    .map(|x| x.inherit(&task.privilege))
+                    .collect();
+                excutors.push((task.name.clone(), hosts, commands));
             }
         }
         Ok(excutors)
     }
+
+    // runs the excutors in order and their hosts in parallel, it stops after the first failed excutor
+    pub fn run(job: &str, inventory: &str, excutors: Vec<Excutor>, open: Opener) -> Result<()> {
+        let reason = Arc::new(Mutex::new(None::<Error>));
+        for (hosts, tasks) in excutors {
+            let mut children = vec![];
+            for (host, vars) in hosts {
+                let tasks = tasks.clone();
+                let reason = reason.clone();
+                let open = open.clone();
+                let inventory = inventory.to_string();
+                children.push(
+                    thread::Builder::new()
+                        .name(format!("{}-{}-{}", host, job, inventory))
+                        .spawn(move || {
+                            let run = || -> Result<()> {
+                                let transport = open(&inventory, &host, &vars)?;
+                                for task in tasks.iter() {
+                                    info!("run {} on {}", task, host);
+                                    task.run_with(transport.as_ref(), &inventory, &host, &vars)?;
+                                }
+                                Ok(())
+                            };
+                            if let Err(e) = run() {
+                                if let Ok(mut reason) = reason.lock() {
+                                    *reason = Some(e);
+                                }
+                            }
+                        })?,
+                );
+            }
+            for it in children {
+                info!("waiting for thread finished...");
+                let _ = it.join();
+            }
+            let mut reason = reason.lock().map_err(|e| Error::Custom(e.to_string()))?;
+            if let Some(e) = reason.take() {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -154,159 +414,202 @@ pub struct Task {
     pub groups: Vec<String>,
     pub commands: Vec<Command>,
     pub vars: Vars,
+    #[serde(flatten)]
+    pub privilege: Privilege,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(tag = "type", rename_all = "camelCase")]
-pub enum Command {
-    Upload { src: String, dest: String },
-    Download { src: String, dest: String },
-    Shell { script: String },
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Method {
+    Sudo,
+    Su,
+    Doas,
 }
 
-impl Command {
-    const LOCALHOST: &'static str = "localhost";
+// become, become_user and become_method of a task or a command, unset ones of a
+// command are inherited from its task
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Privilege {
+    #[serde(rename = "become", default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    #[serde(
+        rename = "become_user",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub user: Option<String>,
+    #[serde(
+        rename = "become_method",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub method: Option<Method>,
+}
 
-    fn parse_ssh_port(vars: &Vars) -> u16 {
-        if let Some(Value::Integer(v)) = vars.get("ssh.port") {
-            return *v as u16;
+impl Privilege {
+    fn inherit(&self, parent: &Self) -> Self {
+        Self {
+            enabled: self.enabled.or(parent.enabled),
+            user: self.user.clone().or_else(|| parent.user.clone()),
+            method: self.method.or(parent.method),
         }
-        22
     }
-    fn parse_ssh_key_file(inventory: &str, vars: &Vars) -> String {
-        if let Some(Value::String(v)) = vars.get("ssh.key-file") {
-            return v.clone();
+
+    // root by sudo unless given, become_user is rendered by the vars of the host
+    fn resolve(&self, vars: &Vars) -> Result<Option<Become>> {
+        if self.enabled != Some(true) {
+            return Ok(None);
         }
-        let key = Path::new(inventory).join("id_rsa");
-        if key.exists() {
-            key.display().to_string()
-        } else {
-            "~/.ssh/id_rsa".to_string()
+        Ok(Some(Become {
+            user: match self.user {
+                Some(ref v) => template_str(v, vars)?,
+                None => "root".to_string(),
+            },
+            method: self.method.unwrap_or(Method::Sudo),
+        }))
+    }
+}
+
+// owner, group, mode and dir_mode of uploaded files and directories, they are applied on
+// the host side, modes could be octal (0644) or symbolic (u=rw,go=r) as chmod takes
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Permission {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir_mode: Option<String>,
+}
+
+impl Permission {
+    fn render(&self, vars: &Vars) -> Result<Self> {
+        let render = |x: &Option<String>| -> Result<Option<String>> {
+            match x {
+                Some(v) => Ok(Some(template_str(v, vars)?)),
+                None => Ok(None),
+            }
+        };
+        Ok(Self {
+            owner: render(&self.owner)?,
+            group: render(&self.group)?,
+            mode: render(&self.mode)?,
+            dir_mode: render(&self.dir_mode)?,
+        })
+    }
+
+    // user:group, user or :group as chown and rsync --chown take
+    pub fn chown(&self) -> Option<String> {
+        match (&self.owner, &self.group) {
+            (None, None) => None,
+            (Some(u), None) => Some(u.clone()),
+            (None, Some(g)) => Some(format!(":{}", g)),
+            (Some(u), Some(g)) => Some(format!("{}:{}", u, g)),
+        }
+    }
+}
+
+// the user to run as and how to switch to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Become {
+    pub user: String,
+    pub method: Method,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Command {
+    Upload {
+        src: String,
+        dest: String,
+        #[serde(flatten)]
+        privilege: Privilege,
+        #[serde(flatten)]
+        permission: Permission,
+    },
+    Download {
+        src: String,
+        dest: String,
+        #[serde(flatten)]
+        privilege: Privilege,
+    },
+    Shell {
+        script: String,
+        #[serde(flatten)]
+        privilege: Privilege,
+    },
+}
+
+impl Command {
+    pub fn privilege(&self) -> &Privilege {
+        match self {
+            Self::Upload { privilege, .. }
+            | Self::Download { privilege, .. }
+            | Self::Shell { privilege, .. } => privilege,
         }
     }
-    fn parse_ssh_user(vars: &Vars) -> String {
-        if let Some(Value::String(v)) = vars.get("ssh.user") {
-            return v.clone();
+
+    fn inherit(&self, parent: &Privilege) -> Self {
+        let mut it = self.clone();
+        match it {
+            Self::Upload {
+                ref mut privilege, ..
+            }
+            | Self::Download {
+                ref mut privilege, ..
+            }
+            | Self::Shell {
+                ref mut privilege, ..
+            } => *privilege = privilege.inherit(parent),
         }
-        "root".to_string()
+        it
     }
+
     pub fn run(&self, inventory: &str, host: &str, vars: &Vars) -> Result<()> {
-        debug!("host {} env: {:?}", host, vars);
-        let user = Self::parse_ssh_user(vars);
-        let port = Self::parse_ssh_port(vars);
-        let key: String = Self::parse_ssh_key_file(inventory, vars);
-        let sh = match vars.get("ssh.shell") {
-            Some(Value::String(v)) => v.clone(),
-            _ => "bash".to_string(),
-        };
+        let transport = transport::open(inventory, host, vars)?;
+        self.run_with(transport.as_ref(), inventory, host, vars)
+    }
 
-        let ssh = format!(
-            "ssh -T -o ConnectTimeout=3 -o ConnectionAttempts=5 -o StrictHostKeyChecking=no -o PasswordAuthentication=no -p {port} -i {key}",
-            port = port,
-            key = key
-        );
+    pub fn run_with(
+        &self,
+        transport: &dyn Transport,
+        inventory: &str,
+        host: &str,
+        vars: &Vars,
+    ) -> Result<()> {
+        debug!("host {} env: {:?}", host, sensitive::redact(vars));
+        let escalate = self.privilege().resolve(vars)?;
+        let escalate = escalate.as_ref();
         match self {
-            Self::Upload { src, dest } => {
-                let src = template_file(inventory, src, vars)?.display().to_string();
+            Self::Upload {
+                src,
+                dest,
+                permission,
+                ..
+            } => {
+                let src = template_file(inventory, src, vars)?;
                 let dest = template_str(dest, vars)?;
-                if host == Self::LOCALHOST {
-                    shell(
-                        host,
-                        ShellCommand::new("rsync")
-                            .arg("-rlptD")
-                            .arg("-v")
-                            .arg(src)
-                            .arg(dest),
-                    )?;
-                } else {
-                    shell(
-                        host,
-                        ShellCommand::new("rsync")
-                            .arg("-rlptD")
-                            .arg("-zz")
-                            .arg("-v")
-                            .arg("-e")
-                            .arg(ssh)
-                            .arg(src)
-                            .arg(format!(
-                                "{user}@{host}:{dest}",
-                                user = user,
-                                host = host,
-                                dest = dest,
-                            )),
-                    )?;
-                }
+                transport.put(&src, &dest, escalate, &permission.render(vars)?)?;
             }
-            Self::Download { src, dest } => {
+            Self::Download { src, dest, .. } => {
                 let src = template_str(src, vars)?;
                 let dest = template_str(dest, vars)?;
                 let dest = {
-                    let it = Path::new("tmp").join("downloads").join(host).join(dest);
-                    {
-                        if let Some(it) = it.parent() {
-                            if !it.exists() {
-                                create_dir_all(it)?;
-                            }
+                    let it = ROOT.join("downloads").join(host).join(dest);
+                    if let Some(it) = it.parent() {
+                        if !it.exists() {
+                            create_dir_all(it)?;
                         }
                     }
-                    it.display().to_string()
+                    it
                 };
-                if host == Self::LOCALHOST {
-                    shell(
-                        host,
-                        ShellCommand::new("rsync")
-                            .arg("-rlptD")
-                            .arg("-v")
-                            .arg(src)
-                            .arg(dest),
-                    )?;
-                } else {
-                    shell(
-                        host,
-                        ShellCommand::new("rsync")
-                            .arg("-rlptD")
-                            .arg("-zz")
-                            .arg("-v")
-                            .arg("-e")
-                            .arg(ssh)
-                            .arg(format!(
-                                "{user}@{host}:{src}",
-                                src = src,
-                                user = user,
-                                host = host,
-                            ))
-                            .arg(dest),
-                    )?;
-                }
+                transport.get(&src, &dest, escalate)?;
             }
-            Self::Shell { script } => {
-                let script = template_file(inventory, script, vars)?
-                    .display()
-                    .to_string();
-                if host == Self::LOCALHOST {
-                    shell(host, ShellCommand::new(&sh).arg(script))?;
-                } else {
-                    shell(
-                        host,
-                        ShellCommand::new("ssh")
-                            .arg("-T")
-                            .arg("-o")
-                            .arg("ConnectTimeout=3")
-                            .arg("-o")
-                            .arg("ConnectionAttempts=5")
-                            .arg("-o")
-                            .arg("StrictHostKeyChecking=no")
-                            .arg("-o")
-                            .arg("PasswordAuthentication=no")
-                            .arg("-p")
-                            .arg(port.to_string())
-                            .arg("-i")
-                            .arg(key)
-                            .arg(format!("{}@{}", user, host))
-                            .arg(format!("{} -s", sh))
-                            .stdin(File::open(script)?),
-                    )?;
-                }
+            Self::Shell { script, .. } => {
+                let script = template_file(inventory, script, vars)?;
+                transport.exec(&script, escalate)?;
             }
         };
         Ok(())
@@ -316,48 +619,398 @@ impl Command {
 impl fmt::Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Self::Upload { src, dest } => write!(f, "upload {} to {}", src, dest),
-            Self::Download { src, dest } => write!(f, "download {} to {}", src, dest),
-            Self::Shell { script } => write!(f, "shell script {}", script),
+            Self::Upload { src, dest, .. } => write!(f, "upload {} to {}", src, dest),
+            Self::Download { src, dest, .. } => write!(f, "download {} to {}", src, dest),
+            Self::Shell { script, .. } => write!(f, "shell script {}", script),
         }
     }
 }
 
+fn built_in(items: &[(&str, &str)]) -> Layer {
+    (
+        BUILT_IN.to_string(),
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), Value::String(v.to_string())))
+            .collect(),
+    )
+}
+
+// the __replace__ markers are kept while the layers are merged and removed at last
+pub fn flatten(layers: &[Layer]) -> Vars {
+    let mut vars = Vars::new();
+    for (_, it) in layers.iter() {
+        merge(&mut vars, it.clone());
+    }
+    vars.into_iter().map(|(k, v)| (k, unmark(v))).collect()
+}
+
+// the layers which set each leaf of the vars (tables are walked by dotted paths), in order,
+// a table with __replace__ = true drops the leaves set before it
+pub fn sources(layers: &[Layer]) -> BTreeMap<Vec<String>, Vec<(&str, &Value)>> {
+    fn walk<'a>(
+        items: &mut BTreeMap<Vec<String>, Vec<(&'a str, &'a Value)>>,
+        name: &'a str,
+        path: Vec<String>,
+        val: &'a Value,
+    ) {
+        let under = |x: &Vec<String>| x.len() > path.len() && x.starts_with(&path);
+        match val {
+            Value::Table(it) if it.keys().any(|k| k != REPLACE) => {
+                items.remove(&path);
+                if it.get(REPLACE) == Some(&Value::Boolean(true)) {
+                    items.retain(|x, _| !under(x));
+                }
+                for (k, v) in it.iter().filter(|(k, _)| *k != REPLACE) {
+                    let mut path = path.clone();
+                    path.push(k.clone());
+                    walk(items, name, path, v);
+                }
+            }
+            _ => {
+                items.retain(|x, _| !under(x));
+                items.entry(path).or_default().push((name, val));
+            }
+        }
+    }
+
+    let mut items = BTreeMap::new();
+    for (name, vars) in layers.iter() {
+        for (key, val) in vars.iter() {
+            walk(&mut items, name, vec![key.clone()], val);
+        }
+    }
+    items
+}
+
+pub(crate) fn flatten_all(items: Vec<Trace>) -> Vec<Host> {
+    items
+        .into_iter()
+        .map(|(host, layers)| (host, flatten(&layers)))
+        .collect()
+}
+
+// renders the vars which contain handlebars expressions, a var is rendered
+// after all the vars it references, "job.name" could also be used as {{job.name}}
+pub fn resolve(vars: &mut Vars) -> Result<()> {
+    let items: BTreeMap<String, Vec<String>> = {
+        let keys: Vec<&String> = vars
+            .iter()
+            .filter(|(_, v)| templated(v))
+            .map(|(k, _)| k)
+            .collect();
+        let re = Regex::new(r"\{\{[^}]*\}\}")?;
+        let token = Regex::new(r"\[([^\]]+)\]|([A-Za-z_][\w.\-]*)")?;
+        let mut items = BTreeMap::new();
+        for key in keys.iter() {
+            let mut deps = Vec::new();
+            let mut buf = String::new();
+            strings(&vars[*key], &mut buf);
+            for block in re.find_iter(&buf) {
+                for it in token.captures_iter(block.as_str()) {
+                    let names = match (it.get(1), it.get(2)) {
+                        (Some(v), _) => vec![v.as_str().to_string()],
+                        (_, Some(v)) => {
+                            let v = v.as_str();
+                            let mut names = vec![v.to_string()];
+                            for (i, _) in v.match_indices('.') {
+                                names.push(v[..i].to_string());
+                            }
+                            names
+                        }
+                        _ => Vec::new(),
+                    };
+                    for name in names {
+                        if keys.iter().any(|x| **x == name) && !deps.contains(&name) {
+                            deps.push(name);
+                        }
+                    }
+                }
+            }
+            items.insert(key.to_string(), deps);
+        }
+        items
+    };
+
+    let mut done = Vec::new();
+    for key in items.keys() {
+        sort_deps(key, &items, &mut Vec::new(), &mut done)?;
+    }
+    for key in done {
+        let ctx = context(vars);
+        if let Some(v) = vars.get_mut(&key) {
+            render_value(v, &ctx)
+                .map_err(|e| Error::Custom(format!("can't render var {}: {}", key, e)))?;
+        }
+    }
+    Ok(())
+}
+
+fn templated(v: &Value) -> bool {
+    match v {
+        Value::String(v) => v.contains("{{"),
+        Value::Array(v) => v.iter().any(templated),
+        Value::Table(v) => v.values().any(templated),
+        _ => false,
+    }
+}
+
+fn strings(v: &Value, buf: &mut String) {
+    match v {
+        Value::String(v) => buf.push_str(v),
+        Value::Array(v) => v.iter().for_each(|x| strings(x, buf)),
+        Value::Table(v) => v.values().for_each(|x| strings(x, buf)),
+        _ => {}
+    }
+}
+
+fn sort_deps(
+    key: &str,
+    items: &BTreeMap<String, Vec<String>>,
+    path: &mut Vec<String>,
+    done: &mut Vec<String>,
+) -> Result<()> {
+    if done.iter().any(|x| x == key) {
+        return Ok(());
+    }
+    if path.iter().any(|x| x == key) {
+        return Err(Error::Custom(format!(
+            "cycle detected in vars: {} -> {}",
+            path.join(" -> "),
+            key
+        )));
+    }
+    path.push(key.to_string());
+    if let Some(deps) = items.get(key) {
+        for it in deps.iter() {
+            sort_deps(it, items, path, done)?;
+        }
+    }
+    path.pop();
+    done.push(key.to_string());
+    Ok(())
+}
+
+// dotted keys are also available as nested paths if they don't clash with other vars
+fn context(vars: &Vars) -> Value {
+    fn alias(cur: &mut toml::value::Table, names: &[&str], val: &Value) {
+        match names {
+            [] => {}
+            [name] => {
+                if !cur.contains_key(*name) {
+                    cur.insert(name.to_string(), val.clone());
+                }
+            }
+            [name, names @ ..] => {
+                if let Value::Table(it) = cur
+                    .entry(name.to_string())
+                    .or_insert_with(|| Value::Table(toml::value::Table::new()))
+                {
+                    alias(it, names, val);
+                }
+            }
+        }
+    }
+
+    let mut ctx: toml::value::Table = vars.clone().into_iter().collect();
+    for (key, val) in vars.iter() {
+        let names = key.split('.').collect::<Vec<_>>();
+        if names.len() > 1 {
+            alias(&mut ctx, &names, val);
+        }
+    }
+    Value::Table(ctx)
+}
+
+fn render_value(v: &mut Value, ctx: &Value) -> Result<()> {
+    match v {
+        Value::String(it) if it.contains("{{") => *it = template_str(it, ctx)?,
+        Value::Array(it) => {
+            for x in it.iter_mut() {
+                render_value(x, ctx)?;
+            }
+        }
+        Value::Table(it) => {
+            for (_, x) in it.iter_mut() {
+                render_value(x, ctx)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// tables are merged recursively unless the overriding table sets __replace__ = true,
+// the marker stays in the result, so a layer built of several files still replaces
+pub fn merge(vars: &mut Vars, other: Vars) {
+    for (k, v) in other {
+        let it = merge_value(vars.remove(&k), v);
+        vars.insert(k, it);
+    }
+}
+
+fn merge_value(cur: Option<Value>, val: Value) -> Value {
+    match (cur, val) {
+        (Some(Value::Table(mut cur)), Value::Table(val))
+            if val.get(REPLACE) != Some(&Value::Boolean(true)) =>
+        {
+            for (k, v) in val {
+                let it = merge_value(cur.remove(&k), v);
+                cur.insert(k, it);
+            }
+            Value::Table(cur)
+        }
+        (_, val) => val,
+    }
+}
+
+fn unmark(val: Value) -> Value {
+    match val {
+        Value::Table(val) => Value::Table(
+            val.into_iter()
+                .filter(|(k, _)| k != REPLACE)
+                .map(|(k, v)| (k, unmark(v)))
+                .collect(),
+        ),
+        val => val,
+    }
+}
+
+#[cfg(not(windows))]
+fn is_executable(file: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    match file.metadata() {
+        Ok(it) => it.is_file() && it.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+#[cfg(windows)]
+fn is_executable(_file: &Path) -> bool {
+    false
+}
+
+// runs the executable and parses its standard output as json or toml
+fn execute<T: DeserializeOwned>(file: &Path) -> Result<T> {
+    info!("run {}", file.display());
+    let file = if file.is_relative() {
+        Path::new(".").join(file)
+    } else {
+        file.to_path_buf()
+    };
+    let out = ShellCommand::new(&file).stderr(Stdio::inherit()).output()?;
+    if !out.status.success() {
+        return Err(Error::Custom(format!(
+            "{} exited with {}",
+            file.display(),
+            out.status
+        )));
+    }
+    let it = if out.stdout.iter().find(|x| !x.is_ascii_whitespace()) == Some(&b'{') {
+        serde_json::from_slice(&out.stdout)?
+    } else {
+        toml::from_slice(&out.stdout)?
+    };
+    Ok(it)
+}
+
+// expands ranges such as node[01:24].lab, 192.168.8.[21:24] or rack-[a:c][1:8:2]
+pub fn expand(host: &str) -> Result<Vec<String>> {
+    let (begin, end) = match (host.find('['), host.find(']')) {
+        (Some(begin), Some(end)) if begin < end => (begin, end),
+        _ => return Ok(vec![host.to_string()]),
+    };
+    let (prefix, range, suffix) = (&host[..begin], &host[begin + 1..end], &host[end + 1..]);
+    let bad = || Error::Custom(format!("bad host range {}", host));
+
+    let items: Vec<&str> = range.split(':').collect();
+    let (first, last, step) = match items.as_slice() {
+        [first, last] => (*first, *last, 1),
+        [first, last, step] => (*first, *last, step.parse::<usize>().map_err(|_| bad())?),
+        _ => return Err(bad()),
+    };
+    if step == 0 {
+        return Err(bad());
+    }
+
+    let mut names = Vec::new();
+    if let (Ok(x), Ok(y)) = (first.parse::<usize>(), last.parse::<usize>()) {
+        if x > y {
+            return Err(bad());
+        }
+        let width = if first.starts_with('0') {
+            first.len()
+        } else {
+            0
+        };
+        for it in (x..=y).step_by(step) {
+            names.push(format!("{:0width$}", it, width = width));
+        }
+    } else {
+        match (first.as_bytes(), last.as_bytes()) {
+            ([x], [y]) if x.is_ascii_alphabetic() && y.is_ascii_alphabetic() && x <= y => {
+                for it in (*x..=*y).step_by(step) {
+                    names.push((it as char).to_string());
+                }
+            }
+            _ => return Err(bad()),
+        }
+    }
+
+    let mut items = Vec::new();
+    for name in names {
+        for suffix in expand(suffix)? {
+            items.push(format!("{}{}{}", prefix, name, suffix));
+        }
+    }
+    Ok(items)
+}
+
 fn parse<P: AsRef<Path>, T: DeserializeOwned>(file: P) -> Result<T> {
     let file = file.as_ref();
     debug!("load file {}", file.display());
-    let mut file = File::open(file)?;
     let mut buf = Vec::new();
-    file.read_to_end(&mut buf)?;
-    let it = toml::from_slice(&buf)?;
-    Ok(it)
-}
-
-fn shell(host: &str, cmd: &mut ShellCommand) -> Result<()> {
-    let root = Path::new("tmp").join("logs");
-    if !root.exists() {
-        create_dir_all(&root)?;
+    File::open(file)?.read_to_end(&mut buf)?;
+    let encrypted = vault::is_encrypted(&buf);
+    if encrypted {
+        debug!("decrypt file {}", file.display());
+        buf = vault::decrypt(std::str::from_utf8(&buf)?)?;
     }
-    let outputs = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)
-        .open(root.join(host))?;
-    {
-        let mut wrt = BufWriter::new(&outputs);
-        writeln!(wrt, "{}: {:?}", Utc::now().naive_local(), cmd)?;
+    let mut it: Value = match file_ext(file) {
+        Some("json") => serde_json::from_slice(&buf)?,
+        Some("yaml") | Some("yml") => serde_yaml::from_slice(&buf)?,
+        _ => toml::from_slice(&buf)?,
+    };
+    vault::reveal(&mut it)?;
+    if encrypted {
+        sensitive::add_value(&it);
     }
-    let errors = outputs.try_clone()?;
+    Ok(it.try_into()?)
+}
 
-    let out = cmd
-        .stdout(Stdio::from(outputs))
-        .stderr(Stdio::from(errors))
-        .spawn()?
-        .wait_with_output()?;
-    if !out.status.success() {
-        return Err(Error::Custom(format!("{:?}", cmd)));
+fn file_ext(file: &Path) -> Option<&str> {
+    file.extension().and_then(|x| x.to_str())
+}
+
+// several formats of the same name are ambiguous, so they are rejected
+fn find_config(root: &Path, name: &str) -> Result<Option<PathBuf>> {
+    let items: Vec<PathBuf> = CONFIG_EXTS
+        .iter()
+        .map(|ext| root.join(format!("{}.{}", name, ext)))
+        .filter(|it| it.is_file())
+        .collect();
+    match items.as_slice() {
+        [] => Ok(None),
+        [it] => Ok(Some(it.clone())),
+        _ => Err(Error::Custom(format!(
+            "found more than one config file: {}",
+            items
+                .iter()
+                .map(|x| x.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
     }
-    Ok(())
 }
 
 fn _template_file<P: AsRef<Path>>(inventory: &str, tpl: P, vars: &Vars) -> Result<Option<PathBuf>> {
@@ -365,47 +1018,116 @@ fn _template_file<P: AsRef<Path>>(inventory: &str, tpl: P, vars: &Vars) -> Resul
     {
         let tpl = Path::new(inventory).join(tpl);
         debug!("try file {}", tpl.display());
+        if tpl.is_dir() {
+            return Ok(Some(template_dir(&tpl, vars)?));
+        }
         if tpl.exists() {
             return Ok(Some(tpl));
         }
     }
     let tpl = Path::new(JOBS).join(tpl);
     debug!("try file {}", tpl.display());
+    if tpl.is_dir() {
+        return Ok(Some(template_dir(&tpl, vars)?));
+    }
     if tpl.exists() {
         return Ok(Some(tpl));
     }
     let tpl = tpl.with_extension(TEMPLATE_EXT);
     debug!("try file {}", tpl.display());
     if tpl.exists() {
-        let root = Path::new("tmp").join("cache");
-        if !root.exists() {
-            create_dir_all(&root)?;
-        }
-        let rdr = root.join(Uuid::new_v4().to_string());
-        {
-            debug!("render {} to {}: {:?}", tpl.display(), rdr.display(), vars);
-            #[cfg(windows)]
-            let rdr = OpenOptions::new().create_new(true).write(true).open(&rdr)?;
-            #[cfg(not(windows))]
-            let rdr = {
-                use std::os::unix::fs::OpenOptionsExt;
-                OpenOptions::new()
-                    .mode(0o400)
-                    .create_new(true)
-                    .write(true)
-                    .open(&rdr)?
-            };
-            let mut reg = Handlebars::new();
-            reg.set_strict_mode(true);
-            let mut tpl = File::open(tpl)?;
-            reg.render_template_source_to_write(&mut tpl, vars, &rdr)?;
-            rdr.sync_all()?;
-        }
+        let rdr = Cache::root()?.join(Uuid::new_v4().to_string());
+        render(&tpl, &rdr, vars)?;
         return Ok(Some(rdr));
     }
 
     Ok(None)
 }
+
+fn render(tpl: &Path, rdr: &Path, vars: &Vars) -> Result<()> {
+    debug!(
+        "render {} to {}: {:?}",
+        tpl.display(),
+        rdr.display(),
+        sensitive::redact(vars)
+    );
+    #[cfg(windows)]
+    let rdr = OpenOptions::new().create_new(true).write(true).open(rdr)?;
+    #[cfg(not(windows))]
+    let rdr = {
+        use std::os::unix::fs::OpenOptionsExt;
+        OpenOptions::new()
+            .mode(0o400)
+            .create_new(true)
+            .write(true)
+            .open(rdr)?
+    };
+    let mut reg = Handlebars::new();
+    reg.set_strict_mode(true);
+    let mut tpl = File::open(tpl)?;
+    reg.render_template_source_to_write(&mut tpl, vars, &rdr)?;
+    rdr.sync_all()?;
+    Ok(())
+}
+
+// copies the directory into the cache with every *.hbs rendered (the extension is stripped),
+// the staging tree keeps the name of the directory (and a trailing /) for rsync;
+// a directory without templates is sent as it is
+fn template_dir(src: &Path, vars: &Vars) -> Result<PathBuf> {
+    fn found(src: &Path) -> Result<bool> {
+        for it in read_dir(src)? {
+            let it = it?.path();
+            let file_type = it.symlink_metadata()?.file_type();
+            if (file_type.is_dir() && found(&it)?)
+                || (file_type.is_file() && file_ext(&it) == Some(TEMPLATE_EXT))
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+    fn walk(src: &Path, dest: &Path, vars: &Vars) -> Result<()> {
+        create_dir_all(dest)?;
+        for it in read_dir(src)? {
+            let it = it?.path();
+            let name = it
+                .file_name()
+                .ok_or_else(|| Error::Custom(format!("bad file name {}", it.display())))?;
+            let file_type = it.symlink_metadata()?.file_type();
+            if file_type.is_dir() {
+                walk(&it, &dest.join(name), vars)?;
+            } else if file_type.is_symlink() {
+                #[cfg(not(windows))]
+                std::os::unix::fs::symlink(read_link(&it)?, dest.join(name))?;
+                #[cfg(windows)]
+                copy(&it, dest.join(name))?;
+            } else if file_ext(&it) == Some(TEMPLATE_EXT) {
+                render(&it, &dest.join(name).with_extension(""), vars)?;
+            } else {
+                copy(&it, dest.join(name))?;
+            }
+        }
+        // at last, the source could be read-only
+        set_permissions(dest, src.metadata()?.permissions())?;
+        Ok(())
+    }
+
+    if !found(src)? {
+        return Ok(src.to_path_buf());
+    }
+    let name = src
+        .file_name()
+        .ok_or_else(|| Error::Custom(format!("bad directory {}", src.display())))?;
+    let root = Cache::root()?.join(Uuid::new_v4().to_string());
+    let dest = root.join(name);
+    debug!("stage {} to {}", src.display(), dest.display());
+    walk(src, &dest, vars)?;
+    if src.to_string_lossy().ends_with('/') {
+        return Ok(PathBuf::from(format!("{}/", dest.display())));
+    }
+    Ok(dest)
+}
+
 fn template_file<P: AsRef<Path>>(inventory: &str, tpl: P, vars: &Vars) -> Result<PathBuf> {
     let tpl = tpl.as_ref();
     if let Some(v) = _template_file(inventory, tpl, vars)? {
@@ -423,10 +1145,12 @@ fn template_file<P: AsRef<Path>>(inventory: &str, tpl: P, vars: &Vars) -> Result
     )))
 }
 
-fn template_str(tpl: &str, vars: &Vars) -> Result<String> {
+// vars, paths and users are plain text, nothing is escaped as html
+fn template_str<T: Serialize>(tpl: &str, vars: &T) -> Result<String> {
     let mut reg = Handlebars::new();
     let name = "";
     reg.set_strict_mode(true);
+    reg.register_escape_fn(no_escape);
     reg.register_template_string(name, tpl)?;
     Ok(reg.render(name, vars)?)
 }
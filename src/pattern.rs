@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use regex::Regex;
+
+use super::{
+    errors::{Error, Result},
+    models::{flatten_all, Group, Host, Layer, Trace, Vars},
+};
+
+// a pattern is a list of terms separated by ':' or ',':
+//   name    - a group or a host
+//   db*     - groups and hosts matching the glob
+//   ~^db\d+ - groups and hosts matching the regex
+//   &term   - intersection
+//   !term   - exclusion
+// a regex term runs to the end of the pattern, so it could contain ':' and ','
+// (such as ~^db\d{1,3}$ or ~^fe80::1$), put it last
+pub fn select(pattern: &str, inventory: &str, parent: Vars) -> Result<Vec<Host>> {
+    Ok(flatten_all(trace(
+        pattern,
+        inventory,
+        vec![("parent".to_string(), parent)],
+    )?))
+}
+
+pub fn trace(pattern: &str, inventory: &str, parent: Vec<Layer>) -> Result<Vec<Trace>> {
+    let mut finder = Finder {
+        inventory,
+        parent,
+        groups: BTreeMap::new(),
+        names: None,
+    };
+
+    let mut union = Vec::new();
+    let mut intersections = Vec::new();
+    let mut exclusions = Vec::new();
+    for term in terms(pattern) {
+        if let Some(term) = term.strip_prefix('&') {
+            intersections.push(finder.find(term)?);
+        } else if let Some(term) = term.strip_prefix('!') {
+            exclusions.push(finder.find(term)?);
+        } else {
+            for it in finder.find(term)? {
+                if !union.iter().any(|(x, _): &Trace| *x == it.0) {
+                    union.push(it);
+                }
+            }
+        }
+    }
+
+    union.retain(|(host, _)| {
+        intersections
+            .iter()
+            .all(|items| items.iter().any(|(x, _)| x == host))
+            && !exclusions
+                .iter()
+                .any(|items| items.iter().any(|(x, _)| x == host))
+    });
+    if union.is_empty() {
+        warn!("no hosts matched by {}@{}", pattern, inventory);
+    }
+    Ok(union)
+}
+
+fn terms(pattern: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut rest = pattern;
+    loop {
+        let term = rest.trim_start();
+        if term.trim_start_matches(&['&', '!'][..]).starts_with('~') {
+            items.push(term.trim());
+            break;
+        }
+        match rest.find(&[':', ','][..]) {
+            Some(i) => {
+                items.push(rest[..i].trim());
+                rest = &rest[i + 1..];
+            }
+            None => {
+                items.push(rest.trim());
+                break;
+            }
+        }
+    }
+    items.retain(|x| !x.is_empty());
+    items
+}
+
+struct Finder<'a> {
+    inventory: &'a str,
+    parent: Vec<Layer>,
+    groups: BTreeMap<String, Vec<Trace>>,
+    names: Option<Vec<String>>,
+}
+
+impl<'a> Finder<'a> {
+    fn names(&mut self) -> Result<Vec<String>> {
+        if let Some(ref it) = self.names {
+            return Ok(it.clone());
+        }
+        let it = Group::names(self.inventory)?;
+        self.names = Some(it.clone());
+        Ok(it)
+    }
+
+    fn group(&mut self, name: &str) -> Result<Vec<Trace>> {
+        if let Some(it) = self.groups.get(name) {
+            return Ok(it.clone());
+        }
+        let it = Group::trace(name, self.inventory, self.parent.clone())?;
+        self.groups.insert(name.to_string(), it.clone());
+        Ok(it)
+    }
+
+    fn find(&mut self, term: &str) -> Result<Vec<Trace>> {
+        let re = if let Some(it) = term.strip_prefix('~') {
+            Regex::new(it)?
+        } else if term.contains(&['*', '?'][..]) {
+            glob(term)?
+        } else {
+            if self.names()?.iter().any(|x| x == term) {
+                return self.group(term);
+            }
+            let items = self.matches(|x| x == term)?;
+            if items.is_empty() {
+                return Err(Error::Custom(format!(
+                    "can't find group or host {}@{}",
+                    term, self.inventory
+                )));
+            }
+            return Ok(items);
+        };
+
+        let mut items = Vec::new();
+        for name in self.names()? {
+            if re.is_match(&name) {
+                for it in self.group(&name)? {
+                    if !items.iter().any(|(x, _): &Trace| *x == it.0) {
+                        items.push(it);
+                    }
+                }
+            }
+        }
+        for it in self.matches(|x| re.is_match(x))? {
+            if !items.iter().any(|(x, _): &Trace| *x == it.0) {
+                items.push(it);
+            }
+        }
+        if items.is_empty() {
+            warn!("no groups or hosts matched by {}", term);
+        }
+        Ok(items)
+    }
+
+    // hosts are looked up across all groups, the first group (by name) wins
+    fn matches<F: Fn(&str) -> bool>(&mut self, f: F) -> Result<Vec<Trace>> {
+        let mut items = Vec::new();
+        for name in self.names()? {
+            for it in self.group(&name)? {
+                if f(&it.0) && !items.iter().any(|(x, _): &Trace| *x == it.0) {
+                    items.push(it);
+                }
+            }
+        }
+        Ok(items)
+    }
+}
+
+pub(crate) fn glob(pattern: &str) -> Result<Regex> {
+    let it = regex::escape(pattern)
+        .replace("\\*", ".*")
+        .replace("\\?", ".");
+    Ok(Regex::new(&format!("^{}$", it))?)
+}
@@ -0,0 +1,157 @@
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::sync::RwLock;
+
+use regex::Regex;
+use toml::Value;
+
+use super::{models::Vars, pattern::glob};
+
+// the var listing extra key patterns (globs) to hide, such as ["db.*", "api-key"]
+pub const SENSITIVE: &str = "sensitive";
+pub const DEFAULTS: &[&str] = &["*password*", "*passwd*", "*secret*", "*token*"];
+pub const MASK: &str = "******";
+// shorter values are only hidden in vars, replacing them in free text does more harm than good
+const MIN_LEN: usize = 4;
+
+lazy_static! {
+    static ref SECRETS: RwLock<BTreeSet<String>> = RwLock::new(BTreeSet::new());
+}
+
+struct Matcher(Vec<Regex>);
+
+impl Matcher {
+    fn new(vars: &Vars) -> Self {
+        let mut items: Vec<String> = DEFAULTS.iter().map(|x| x.to_string()).collect();
+        match vars.get(SENSITIVE) {
+            Some(Value::String(v)) => items.push(v.clone()),
+            Some(Value::Array(v)) => {
+                for it in v.iter() {
+                    if let Value::String(it) = it {
+                        items.push(it.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+        Self(
+            items
+                .iter()
+                .filter_map(|x| glob(&x.to_lowercase()).ok())
+                .collect(),
+        )
+    }
+
+    // a nested key matches by its leaf name or by its dotted path
+    fn is_match(&self, path: &str, key: &str) -> bool {
+        let (path, key) = (path.to_lowercase(), key.to_lowercase());
+        self.0.iter().any(|x| x.is_match(&path) || x.is_match(&key))
+    }
+}
+
+fn redact_value(matcher: &Matcher, path: &str, key: &str, val: &Value) -> Value {
+    if matcher.is_match(path, key) {
+        return Value::String(MASK.to_string());
+    }
+    match val {
+        Value::String(v) if is_secret(v) => Value::String(MASK.to_string()),
+        Value::Table(it) => Value::Table(
+            it.iter()
+                .map(|(k, v)| {
+                    (
+                        k.clone(),
+                        redact_value(matcher, &format!("{}.{}", path, k), k, v),
+                    )
+                })
+                .collect(),
+        ),
+        _ => val.clone(),
+    }
+}
+
+fn is_secret(val: &str) -> bool {
+    match SECRETS.read() {
+        Ok(it) => it.contains(val),
+        Err(_) => false,
+    }
+}
+
+// a copy of the vars safe for logs and outputs, values from the vault are hidden too
+pub fn redact(vars: &Vars) -> Vars {
+    let matcher = Matcher::new(vars);
+    vars.iter()
+        .map(|(k, v)| (k.clone(), redact_value(&matcher, k, k, v)))
+        .collect()
+}
+
+fn collect(val: &Value, items: &mut Vec<String>) {
+    match val {
+        Value::String(v) => items.push(v.clone()),
+        Value::Array(v) => v.iter().for_each(|x| collect(x, items)),
+        Value::Table(v) => v.values().for_each(|x| collect(x, items)),
+        Value::Integer(v) => items.push(v.to_string()),
+        Value::Float(v) => items.push(v.to_string()),
+        _ => {}
+    }
+}
+
+// remembers the sensitive values of the vars, so that mask could hide them in any text,
+// the key patterns are read from rules (usually the vars themselves)
+pub fn register(vars: &Vars, rules: &Vars) {
+    fn walk(matcher: &Matcher, path: &str, key: &str, val: &Value, items: &mut Vec<String>) {
+        if matcher.is_match(path, key) {
+            collect(val, items);
+        } else if let Value::Table(it) = val {
+            for (k, v) in it.iter() {
+                walk(matcher, &format!("{}.{}", path, k), k, v, items);
+            }
+        }
+    }
+
+    let matcher = Matcher::new(rules);
+    let mut items = Vec::new();
+    for (k, v) in vars.iter() {
+        walk(&matcher, k, k, v, &mut items);
+    }
+    add(items);
+}
+
+// every string in the value is a secret, such as the content of an encrypted file
+pub fn add_value(val: &Value) {
+    fn walk(val: &Value, items: &mut Vec<String>) {
+        match val {
+            Value::String(v) => items.push(v.clone()),
+            Value::Array(v) => v.iter().for_each(|x| walk(x, items)),
+            Value::Table(v) => v.values().for_each(|x| walk(x, items)),
+            _ => {}
+        }
+    }
+    let mut items = Vec::new();
+    walk(val, &mut items);
+    add(items);
+}
+
+pub fn add<I: IntoIterator<Item = String>>(items: I) {
+    if let Ok(mut secrets) = SECRETS.write() {
+        for it in items {
+            if it.len() >= MIN_LEN {
+                secrets.insert(it);
+            }
+        }
+    }
+}
+
+pub fn mask(text: &str) -> String {
+    let mut text = text.to_string();
+    if let Ok(secrets) = SECRETS.read() {
+        // longer secrets first, so a secret containing another one is hidden as a whole
+        let mut items: Vec<&String> = secrets.iter().collect();
+        items.sort_by_key(|x| Reverse(x.len()));
+        for it in items {
+            if text.contains(it.as_str()) {
+                text = text.replace(it.as_str(), MASK);
+            }
+        }
+    }
+    text
+}
@@ -0,0 +1,1185 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::env::{self, current_dir};
+use std::fs::{create_dir_all, read_dir, read_to_string, write, File, OpenOptions};
+use std::io::{prelude::*, BufWriter};
+use std::path::{Path, PathBuf};
+use std::process::{Command as ShellCommand, Stdio};
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use toml::Value;
+use uuid::Uuid;
+
+use super::{
+    cache::Cache,
+    errors::{Error, Result},
+    models::{resolve, Become, Inventory, Method, Permission, Vars},
+    pattern, sensitive, ROOT,
+};
+
+pub const CONNECTION: &str = "connection";
+pub const LOCALHOST: &str = "localhost";
+pub const AUTH: &str = "ssh.auth";
+const ASKPASS_PASSWORD_FILE: &str = "AXIS_SSH_PASSWORD_FILE";
+pub const HOST_KEY_CHECKING: &str = "ssh.host-key-checking";
+pub const KNOWN_HOSTS: &str = "known_hosts";
+// idle seconds before a master connection exits by itself, in case it's never closed
+const CONTROL_PERSIST: u32 = 600;
+
+lazy_static! {
+    static ref PASSWORDS: Mutex<BTreeMap<String, PathBuf>> = Mutex::new(BTreeMap::new());
+}
+
+// opens the transport of a host by inventory, host and vars
+pub type Opener = Arc<dyn Fn(&str, &str, &Vars) -> Result<Box<dyn Transport>> + Send + Sync>;
+
+// how commands reach a host, host is the name in the group (used for logs)
+// escalate is the user (and the method) to run as on the host side
+pub trait Transport {
+    // runs a local script file by the shell of the host
+    fn exec(&self, script: &Path, escalate: Option<&Become>) -> Result<()>;
+    // uploads a local file or directory
+    fn put(
+        &self,
+        src: &Path,
+        dest: &str,
+        escalate: Option<&Become>,
+        permission: &Permission,
+    ) -> Result<()>;
+    // downloads a remote file or directory
+    fn get(&self, src: &str, dest: &Path, escalate: Option<&Become>) -> Result<()>;
+}
+
+// the arguments before a command to run it as the become user, su only runs a
+// command line so it can't be used by file transfers
+fn prefix(escalate: Option<&Become>) -> Result<Vec<String>> {
+    Ok(match escalate {
+        None => Vec::new(),
+        Some(Become {
+            user,
+            method: Method::Sudo,
+        }) => vec![
+            "sudo".to_string(),
+            "-n".to_string(),
+            "-u".to_string(),
+            user.clone(),
+            "--".to_string(),
+        ],
+        Some(Become {
+            user,
+            method: Method::Doas,
+        }) => vec![
+            "doas".to_string(),
+            "-n".to_string(),
+            "-u".to_string(),
+            user.clone(),
+        ],
+        Some(Become {
+            method: Method::Su, ..
+        }) => {
+            return Err(Error::Custom(
+                "become_method su only works for shell scripts, use sudo or doas".to_string(),
+            ))
+        }
+    })
+}
+
+// the shell reads the script from stdin, the rendered script is private to the current user
+fn shell_argv(shell: &str, escalate: Option<&Become>) -> Result<Vec<String>> {
+    let it = vec![shell.to_string(), "-s".to_string()];
+    if let Some(Become {
+        user,
+        method: Method::Su,
+    }) = escalate
+    {
+        return Ok(vec![
+            "su".to_string(),
+            user.clone(),
+            "-c".to_string(),
+            it.join(" "),
+        ]);
+    }
+    let mut items = prefix(escalate)?;
+    items.extend(it);
+    Ok(items)
+}
+
+// only the uploaded files and directories are changed, not dest itself (unless it's
+// uploaded), --chmod needs rsync 3.1 or later
+fn rsync_permission(cmd: &mut ShellCommand, permission: &Permission) {
+    if let Some(it) = permission.chown() {
+        cmd.arg(format!("--chown={}", it));
+    }
+    let mut items = Vec::new();
+    for (kind, mode) in &[("D", &permission.dir_mode), ("F", &permission.mode)] {
+        if let Some(mode) = mode {
+            items.extend(mode.split(',').map(|x| format!("{}{}", kind, x.trim())));
+        }
+    }
+    if !items.is_empty() {
+        cmd.arg(format!("--chmod={}", items.join(",")));
+    }
+}
+
+// the same for the files uploaded by tar, target is the uploaded file or directory
+fn tar_permission(target: &str, permission: &Permission) -> String {
+    let mut script = String::new();
+    if let Some(it) = permission.chown() {
+        script.push_str(&format!(" && chown -R {} {}", quote(&it), target));
+    }
+    if let Some(ref it) = permission.dir_mode {
+        script.push_str(&format!(
+            " && find {} -type d -exec chmod {} {{}} +",
+            target,
+            quote(it)
+        ));
+    }
+    if let Some(ref it) = permission.mode {
+        script.push_str(&format!(
+            " && find {} -type f -exec chmod {} {{}} +",
+            target,
+            quote(it)
+        ));
+    }
+    script
+}
+
+// for the command line of ssh
+fn quote(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:@%+,".contains(c))
+    {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+// rsync splits the -e command by itself, a quote inside quotes is doubled
+fn rsync_quote(arg: &str) -> String {
+    let it = quote(arg);
+    if it == arg {
+        return it;
+    }
+    format!("'{}'", arg.replace('\'', "''"))
+}
+
+// the connection var could be local, ssh or mock, by default localhost is local
+fn connection(host: &str, vars: &Vars) -> Result<String> {
+    match vars.get(CONNECTION) {
+        Some(Value::String(v)) => match v.as_str() {
+            "local" | "ssh" | "mock" => Ok(v.clone()),
+            v => Err(Error::Custom(format!(
+                "unknown connection {} of {}",
+                v, host
+            ))),
+        },
+        Some(v) => Err(Error::Custom(format!("bad connection {} of {}", v, host))),
+        None if Ssh::parse_host(host, vars) == LOCALHOST => Ok("local".to_string()),
+        None => Ok("ssh".to_string()),
+    }
+}
+
+pub fn open(inventory: &str, host: &str, vars: &Vars) -> Result<Box<dyn Transport>> {
+    let it: Box<dyn Transport> = match connection(host, vars)?.as_str() {
+        "local" => Box::new(Local::new(host, vars)),
+        "mock" => Box::new(Mock::new(host)),
+        _ => Box::new(Ssh::new(inventory, host, vars)?),
+    };
+    Ok(it)
+}
+
+fn parse_shell(vars: &Vars) -> String {
+    match vars.get("ssh.shell") {
+        Some(Value::String(v)) => v.clone(),
+        _ => "bash".to_string(),
+    }
+}
+
+pub struct Local {
+    host: String,
+    shell: String,
+    rsync: Cell<Option<bool>>,
+}
+
+impl Local {
+    pub fn new(host: &str, vars: &Vars) -> Self {
+        Self {
+            host: host.to_string(),
+            shell: parse_shell(vars),
+            rsync: Cell::new(None),
+        }
+    }
+}
+
+impl Local {
+    fn rsync(escalate: Option<&Become>) -> Result<ShellCommand> {
+        let mut items = prefix(escalate)?;
+        items.push("rsync".to_string());
+        let mut it = ShellCommand::new(&items[0]);
+        it.args(&items[1..]).arg("-rlptD").arg("-v");
+        Ok(it)
+    }
+}
+
+impl Remote for Local {
+    fn host(&self) -> &str {
+        &self.host
+    }
+    fn command(&self, argv: &[String]) -> Result<ShellCommand> {
+        let mut it = ShellCommand::new(&argv[0]);
+        it.args(&argv[1..]);
+        Ok(it)
+    }
+    fn rsync_found(&self) -> &Cell<Option<bool>> {
+        &self.rsync
+    }
+}
+
+impl Transport for Local {
+    fn exec(&self, script: &Path, escalate: Option<&Become>) -> Result<()> {
+        shell(
+            &self.host,
+            self.command(&shell_argv(&self.shell, escalate)?)?
+                .stdin(File::open(script)?),
+        )
+    }
+    fn put(
+        &self,
+        src: &Path,
+        dest: &str,
+        escalate: Option<&Become>,
+        permission: &Permission,
+    ) -> Result<()> {
+        if !has_rsync(self)? {
+            return tar_put(self, src, dest, escalate, permission);
+        }
+        let mut it = Self::rsync(escalate)?;
+        rsync_permission(&mut it, permission);
+        shell(&self.host, it.arg(src).arg(dest))
+    }
+    fn get(&self, src: &str, dest: &Path, escalate: Option<&Become>) -> Result<()> {
+        if !has_rsync(self)? {
+            return tar_get(self, src, dest, escalate);
+        }
+        shell(&self.host, Self::rsync(escalate)?.arg(src).arg(dest))
+    }
+}
+
+pub struct Ssh {
+    host: String,
+    addr: String,
+    user: String,
+    port: u16,
+    auth: Auth,
+    shell: String,
+    control: Option<PathBuf>,
+    checking: HostKeyChecking,
+    known_hosts: PathBuf,
+    jump: Vec<String>,
+    rsync: Cell<Option<bool>>,
+}
+
+impl Ssh {
+    pub fn new(inventory: &str, host: &str, vars: &Vars) -> Result<Self> {
+        Ok(Self {
+            host: host.to_string(),
+            addr: Self::parse_host(host, vars),
+            user: Self::parse_user(vars),
+            port: Self::parse_port(vars),
+            auth: Auth::parse(inventory, host, vars)?,
+            shell: parse_shell(vars),
+            control: Cache::sockets(),
+            checking: HostKeyChecking::parse(host, vars)?,
+            known_hosts: known_hosts(inventory),
+            jump: Self::parse_jump(host, vars)?,
+            rsync: Cell::new(None),
+        })
+    }
+
+    fn parse_port(vars: &Vars) -> u16 {
+        if let Some(Value::Integer(v)) = vars.get("ssh.port") {
+            return *v as u16;
+        }
+        22
+    }
+    // the name in the group is only an alias when ssh.host is given
+    fn parse_host(host: &str, vars: &Vars) -> String {
+        if let Some(Value::String(v)) = vars.get("ssh.host") {
+            return v.clone();
+        }
+        host.to_string()
+    }
+    // ssh.jump is a [user@]host[:port] or a chain of them, the first one is connected first
+    fn parse_jump(host: &str, vars: &Vars) -> Result<Vec<String>> {
+        match vars.get("ssh.jump") {
+            None => Ok(Vec::new()),
+            Some(Value::String(v)) => Ok(vec![v.clone()]),
+            Some(Value::Array(items)) => items
+                .iter()
+                .map(|x| match x {
+                    Value::String(v) => Ok(v.clone()),
+                    v => Err(Error::Custom(format!("bad ssh.jump {} of {}", v, host))),
+                })
+                .collect(),
+            Some(v) => Err(Error::Custom(format!("bad ssh.jump {} of {}", v, host))),
+        }
+    }
+    fn parse_user(vars: &Vars) -> String {
+        if let Some(Value::String(v)) = vars.get("ssh.user") {
+            return v.clone();
+        }
+        "root".to_string()
+    }
+
+    pub fn options(&self) -> Vec<String> {
+        let mut items = vec!["-T".to_string(), "-p".to_string(), self.port.to_string()];
+        items.extend(self.common());
+        if let Some(it) = self.proxy(self.jump.len()) {
+            items.push("-o".to_string());
+            items.push(format!("ProxyCommand={}", it));
+        }
+        // one master connection per host for the whole run, ssh and rsync share it
+        if let Some(ref it) = self.control {
+            items.extend(vec![
+                "-o".to_string(),
+                "ControlMaster=auto".to_string(),
+                "-o".to_string(),
+                format!("ControlPath={}", it.join("%C").display()),
+                "-o".to_string(),
+                format!("ControlPersist={}", CONTROL_PERSIST),
+            ]);
+        }
+        items
+    }
+
+    // the options shared by the host and its jump hosts
+    fn common(&self) -> Vec<String> {
+        let mut items = vec![
+            "-o".to_string(),
+            "ConnectTimeout=3".to_string(),
+            "-o".to_string(),
+            "ConnectionAttempts=5".to_string(),
+        ];
+        items.extend(match self.auth {
+            Auth::Key(Some(ref key)) => vec![
+                "-o".to_string(),
+                "PasswordAuthentication=no".to_string(),
+                "-o".to_string(),
+                "IdentitiesOnly=yes".to_string(),
+                "-i".to_string(),
+                key.clone(),
+            ],
+            Auth::Key(None) | Auth::Agent => {
+                vec!["-o".to_string(), "PasswordAuthentication=no".to_string()]
+            }
+            Auth::Password(_) => vec![
+                "-o".to_string(),
+                "PubkeyAuthentication=no".to_string(),
+                "-o".to_string(),
+                "PreferredAuthentications=password,keyboard-interactive".to_string(),
+                "-o".to_string(),
+                "NumberOfPasswordPrompts=1".to_string(),
+            ],
+        });
+        items.extend(match self.checking {
+            HostKeyChecking::Strict => vec![
+                "-o".to_string(),
+                "StrictHostKeyChecking=yes".to_string(),
+                "-o".to_string(),
+                format!("UserKnownHostsFile={}", self.known_hosts.display()),
+            ],
+            HostKeyChecking::AcceptNew => vec![
+                "-o".to_string(),
+                "StrictHostKeyChecking=accept-new".to_string(),
+                "-o".to_string(),
+                format!("UserKnownHostsFile={}", self.known_hosts.display()),
+            ],
+            HostKeyChecking::Off => vec![
+                "-o".to_string(),
+                "StrictHostKeyChecking=no".to_string(),
+                "-o".to_string(),
+                "UserKnownHostsFile=/dev/null".to_string(),
+            ],
+        });
+        items
+    }
+
+    // the jump hosts are reached by ssh -W with the same options (key, known hosts and checking),
+    // the last one through the ones before it; ssh expands % in a proxy command, so it's doubled
+    fn proxy(&self, len: usize) -> Option<String> {
+        if len == 0 {
+            return None;
+        }
+        let (addr, user, port) = Self::split_jump(&self.jump[len - 1]);
+        let mut items = vec!["ssh".to_string(), "-p".to_string(), port.to_string()];
+        items.extend(self.common());
+        if let Some(user) = user {
+            items.push("-l".to_string());
+            items.push(user.to_string());
+        }
+        if let Some(it) = self.proxy(len - 1) {
+            items.push("-o".to_string());
+            items.push(format!("ProxyCommand={}", it));
+        }
+        items.push(addr.to_string());
+        Some(format!(
+            "{} -W %h:%p",
+            items
+                .iter()
+                .map(|x| quote(x))
+                .collect::<Vec<_>>()
+                .join(" ")
+                .replace('%', "%%")
+        ))
+    }
+
+    // [user@]host[:port]
+    fn split_jump(jump: &str) -> (&str, Option<&str>, &str) {
+        let (user, rest) = match jump.rfind('@') {
+            Some(i) => (Some(&jump[..i]), &jump[i + 1..]),
+            None => (None, jump),
+        };
+        match rest.rfind(':') {
+            Some(i) if !rest[..i].contains(':') => (&rest[..i], user, &rest[i + 1..]),
+            _ => (rest, user, "22"),
+        }
+    }
+
+    fn target(&self, path: &str) -> String {
+        format!("{}@{}:{}", self.user, self.addr, path)
+    }
+
+    // the askpass script prints the password file, only its path is in the environment
+    fn command(&self, program: &str) -> Result<ShellCommand> {
+        let mut it = ShellCommand::new(program);
+        if let Auth::Password(ref password) = self.auth {
+            it.env("SSH_ASKPASS", askpass()?)
+                .env("SSH_ASKPASS_REQUIRE", "force")
+                .env(
+                    ASKPASS_PASSWORD_FILE,
+                    password_file(&self.user, &self.addr, password.as_deref())?,
+                );
+        }
+        Ok(it)
+    }
+
+    // the remote rsync runs as the become user
+    fn rsync(&self, escalate: Option<&Become>) -> Result<ShellCommand> {
+        let mut it = self.command("rsync")?;
+        if escalate.is_some() {
+            let mut items = prefix(escalate)?;
+            items.push("rsync".to_string());
+            it.arg(format!("--rsync-path={}", items.join(" ")));
+        }
+        it.arg("-rlptD").arg("-zz").arg("-v").arg("-e").arg(format!(
+            "ssh {}",
+            self.options()
+                .iter()
+                .map(|x| rsync_quote(x))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ));
+        Ok(it)
+    }
+}
+
+// ssh.auth: key (ssh.key-file, then id_ed25519 or id_rsa in the inventory, else the
+// agent and the defaults of ssh), agent (the agent only) or password (ssh.password,
+// could be stored in the vault, else it's asked once for each user@host)
+enum Auth {
+    Key(Option<String>),
+    Agent,
+    Password(Option<String>),
+}
+
+impl Auth {
+    fn parse(inventory: &str, host: &str, vars: &Vars) -> Result<Self> {
+        match vars.get(AUTH) {
+            None => Ok(Self::Key(Self::key_file(inventory, vars))),
+            Some(Value::String(v)) if v == "key" => Ok(Self::Key(Self::key_file(inventory, vars))),
+            Some(Value::String(v)) if v == "agent" => {
+                if env::var_os("SSH_AUTH_SOCK").is_none() {
+                    return Err(Error::Custom(format!(
+                        "ssh-agent isn't running, it's needed by {}",
+                        host
+                    )));
+                }
+                Ok(Self::Agent)
+            }
+            Some(Value::String(v)) if v == "password" => {
+                Ok(Self::Password(match vars.get("ssh.password") {
+                    Some(Value::String(v)) => Some(v.clone()),
+                    _ => None,
+                }))
+            }
+            Some(v) => Err(Error::Custom(format!("bad {} {} of {}", AUTH, v, host))),
+        }
+    }
+
+    fn key_file(inventory: &str, vars: &Vars) -> Option<String> {
+        if let Some(Value::String(v)) = vars.get("ssh.key-file") {
+            return Some(expand_home(v));
+        }
+        let root = Path::new(inventory);
+        if !root.is_dir() {
+            return None;
+        }
+        ["id_ed25519", "id_rsa"]
+            .iter()
+            .map(|x| root.join(x))
+            .find(|x| x.exists())
+            .map(|x| x.display().to_string())
+    }
+}
+
+// ssh doesn't expand ~ in the -i argument
+fn expand_home(path: &str) -> String {
+    if let Some(rest) = path.strip_prefix("~/") {
+        if let Some(home) = env::var_os("HOME") {
+            return Path::new(&home).join(rest).display().to_string();
+        }
+    }
+    path.to_string()
+}
+
+// saves the password (given, or asked on the terminal) of user@addr into a private file,
+// hosts run in parallel so the prompts are serialized
+fn password_file(user: &str, addr: &str, password: Option<&str>) -> Result<PathBuf> {
+    let mut cache = PASSWORDS.lock().map_err(|e| Error::Custom(e.to_string()))?;
+    let key = format!("{}@{}", user, addr);
+    if let Some(it) = cache.get(&key) {
+        return Ok(it.clone());
+    }
+    let password = match password {
+        Some(v) => v.to_string(),
+        None => {
+            let it = rpassword::read_password_from_tty(Some(&format!("password of {}: ", key)))?;
+            sensitive::add(vec![it.clone()]);
+            it
+        }
+    };
+    let file = Cache::secrets()?.join(Uuid::new_v4().to_string());
+    private_file(&file, 0o600, password.as_bytes())?;
+    cache.insert(key, file.clone());
+    Ok(file)
+}
+
+// a script printing the password file given by the environment
+fn askpass() -> Result<PathBuf> {
+    let _lock = PASSWORDS.lock().map_err(|e| Error::Custom(e.to_string()))?;
+    let file = Cache::root()?.join("askpass");
+    if !file.exists() {
+        let buf = format!("#!/bin/sh\ncat \"${}\"\n", ASKPASS_PASSWORD_FILE);
+        private_file(&file, 0o700, buf.as_bytes())?;
+    }
+    Ok(file)
+}
+
+fn private_file(file: &Path, mode: u32, buf: &[u8]) -> Result<()> {
+    #[cfg(not(windows))]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut it = OpenOptions::new()
+            .mode(mode)
+            .create_new(true)
+            .write(true)
+            .open(file)?;
+        it.write_all(buf)?;
+    }
+    #[cfg(windows)]
+    {
+        let _ = mode;
+        write(file, buf)?;
+    }
+    Ok(())
+}
+
+// ssh.host-key-checking: strict (only keys in the known_hosts file of the inventory),
+// accept-new (trust on first use, the default) or off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKeyChecking {
+    Strict,
+    AcceptNew,
+    Off,
+}
+
+impl HostKeyChecking {
+    fn parse(host: &str, vars: &Vars) -> Result<Self> {
+        match vars.get(HOST_KEY_CHECKING) {
+            None => Ok(Self::AcceptNew),
+            Some(Value::String(v)) if v == "strict" => Ok(Self::Strict),
+            Some(Value::String(v)) if v == "accept-new" => Ok(Self::AcceptNew),
+            Some(Value::String(v)) if v == "off" => Ok(Self::Off),
+            Some(v) => Err(Error::Custom(format!(
+                "bad {} {} of {}",
+                HOST_KEY_CHECKING, v, host
+            ))),
+        }
+    }
+}
+
+// <inventory>/known_hosts, or <inventory>.known_hosts for a single-file inventory
+pub fn known_hosts(inventory: &str) -> PathBuf {
+    let it = Path::new(inventory);
+    let it = if it.is_file() {
+        it.with_extension(KNOWN_HOSTS)
+    } else {
+        it.join(KNOWN_HOSTS)
+    };
+    match current_dir() {
+        Ok(cur) if it.is_relative() => cur.join(it),
+        _ => it,
+    }
+}
+
+// appends the keys (by ssh-keyscan) of the ssh hosts which aren't in the known_hosts file yet,
+// returns the hosts which couldn't be scanned (with the reasons); ssh-keyscan can't pass
+// jump hosts, their keys are accepted on the first connection (unless ssh.host-key-checking
+// is strict) or added by hand
+pub fn scan(inventory: &str) -> Result<Vec<String>> {
+    let file = known_hosts(inventory);
+    let mut lines: Vec<String> = if file.exists() {
+        read_to_string(&file)?
+            .lines()
+            .map(|x| x.to_string())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let mut found = 0;
+    let mut skipped = Vec::new();
+    for (host, mut vars) in pattern::select("*", inventory, Inventory::all_vars(inventory)?)? {
+        resolve(&mut vars)?;
+        if connection(&host, &vars)? != "ssh" {
+            continue;
+        }
+        // only the address, ssh-keyscan needs no authentication
+        let (addr, port) = (Ssh::parse_host(&host, &vars), Ssh::parse_port(&vars));
+        let jump = Ssh::parse_jump(&host, &vars)?;
+        if !jump.is_empty() {
+            skipped.push(format!("{} (behind {})", host, jump.join(", ")));
+            continue;
+        }
+        info!("scan {}:{} of {}", addr, port, host);
+        let out = ShellCommand::new("ssh-keyscan")
+            .arg("-p")
+            .arg(port.to_string())
+            .arg(&addr)
+            .stderr(Stdio::null())
+            .output()?;
+        let keys: Vec<String> = String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty() && !x.starts_with('#'))
+            .collect();
+        if keys.is_empty() {
+            skipped.push(format!("{} (no keys from {}:{})", host, addr, port));
+        }
+        for it in keys {
+            if !lines.contains(&it) {
+                lines.push(it);
+                found += 1;
+            }
+        }
+    }
+    if let Some(it) = file.parent() {
+        create_dir_all(it)?;
+    }
+    write(&file, format!("{}\n", lines.join("\n")))?;
+    info!("{} new keys saved into {}", found, file.display());
+    Ok(skipped)
+}
+
+impl Remote for Ssh {
+    fn host(&self) -> &str {
+        &self.host
+    }
+    fn command(&self, argv: &[String]) -> Result<ShellCommand> {
+        let mut it = Ssh::command(self, "ssh")?;
+        it.args(self.options())
+            .arg(format!("{}@{}", self.user, self.addr))
+            .arg(argv.iter().map(|x| quote(x)).collect::<Vec<_>>().join(" "));
+        Ok(it)
+    }
+    fn rsync_found(&self) -> &Cell<Option<bool>> {
+        &self.rsync
+    }
+}
+
+impl Transport for Ssh {
+    // su reads the password from a terminal, ssh -T has none, so only root could switch
+    fn exec(&self, script: &Path, escalate: Option<&Become>) -> Result<()> {
+        if let Some(Become {
+            method: Method::Su, ..
+        }) = escalate
+        {
+            if self.user != "root" {
+                return Err(Error::Custom(format!(
+                    "become_method su needs the root ssh user on {} (not {}), use sudo or doas",
+                    self.host, self.user
+                )));
+            }
+        }
+        shell(
+            &self.host,
+            Remote::command(self, &shell_argv(&self.shell, escalate)?)?.stdin(File::open(script)?),
+        )
+    }
+    fn put(
+        &self,
+        src: &Path,
+        dest: &str,
+        escalate: Option<&Become>,
+        permission: &Permission,
+    ) -> Result<()> {
+        if !has_rsync(self)? {
+            return tar_put(self, src, dest, escalate, permission);
+        }
+        let mut it = self.rsync(escalate)?;
+        rsync_permission(&mut it, permission);
+        shell(&self.host, it.arg(src).arg(self.target(dest)))
+    }
+    fn get(&self, src: &str, dest: &Path, escalate: Option<&Become>) -> Result<()> {
+        if !has_rsync(self)? {
+            return tar_get(self, src, dest, escalate);
+        }
+        shell(
+            &self.host,
+            self.rsync(escalate)?.arg(self.target(src)).arg(dest),
+        )
+    }
+}
+
+// a shell on the host side, for the file transfers without rsync
+trait Remote {
+    fn host(&self) -> &str;
+    // runs the command line on the host
+    fn command(&self, argv: &[String]) -> Result<ShellCommand>;
+    // whether rsync is installed on the host, it's detected on the first transfer
+    fn rsync_found(&self) -> &Cell<Option<bool>>;
+}
+
+fn has_rsync(remote: &dyn Remote) -> Result<bool> {
+    if let Some(it) = remote.rsync_found().get() {
+        return Ok(it);
+    }
+    // 127 (or 1 of some shells) is a missing rsync, 255 is a failed ssh connection
+    let status = remote
+        .command(&[
+            "sh".to_string(),
+            "-c".to_string(),
+            "command -v rsync".to_string(),
+        ])?
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    let it = match status.code() {
+        Some(0) => true,
+        Some(1) | Some(127) => {
+            warn!(
+                "rsync isn't installed on {}, fall back to tar over the shell",
+                remote.host()
+            );
+            false
+        }
+        _ => {
+            return Err(Error::Custom(format!(
+                "can't detect rsync on {}: {}",
+                remote.host(),
+                status
+            )))
+        }
+    };
+    remote.rsync_found().set(Some(it));
+    Ok(it)
+}
+
+// sh -c <script> as the become user
+fn sh(escalate: Option<&Become>, script: String) -> Result<Vec<String>> {
+    let mut it = prefix(escalate)?;
+    it.extend(vec!["sh".to_string(), "-c".to_string(), script]);
+    Ok(it)
+}
+
+// the same as rsync: a directory is put into dest (only its content with a trailing /),
+// a file is put into dest if it's a directory, else it's saved as dest
+fn tar_put(
+    remote: &dyn Remote,
+    src: &Path,
+    dest: &str,
+    escalate: Option<&Become>,
+    permission: &Permission,
+) -> Result<()> {
+    let d = quote(dest);
+    if src.is_dir() {
+        let mut local = ShellCommand::new("tar");
+        let target = if src.to_string_lossy().ends_with('/') {
+            local.arg("-C").arg(src).arg("-cf").arg("-").arg(".");
+            d.clone()
+        } else {
+            let name = file_name(src)?;
+            local
+                .arg("-C")
+                .arg(parent(src))
+                .arg("-cf")
+                .arg("-")
+                .arg(&name);
+            quote(&format!("{}/{}", dest.trim_end_matches('/'), name))
+        };
+        return pipe(
+            remote.host(),
+            &mut local,
+            &mut remote.command(&sh(
+                escalate,
+                format!(
+                    "mkdir -p {d} && tar -C {d} --no-same-owner -xpf -{p}",
+                    d = d,
+                    p = tar_permission(&target, permission)
+                ),
+            )?)?,
+        );
+    }
+    let mut script = if dest.ends_with('/') {
+        format!("mkdir -p {} && ", d)
+    } else {
+        String::new()
+    };
+    script.push_str(&format!(
+        "if [ -d {d} ]; then f={d}/{n}; else mkdir -p \"$(dirname {d})\" && f={d}; fi && cat > \"$f\"",
+        d = d,
+        n = quote(&file_name(src)?)
+    ));
+    #[cfg(not(windows))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        script.push_str(&format!(
+            " && chmod {:o} \"$f\"",
+            src.metadata()?.permissions().mode() & 0o7777
+        ));
+    }
+    script.push_str(&tar_permission("\"$f\"", permission));
+    pipe(
+        remote.host(),
+        ShellCommand::new("cat").arg(src),
+        &mut remote.command(&sh(escalate, script)?)?,
+    )
+}
+
+// the same as rsync: a directory is saved into dest (only its content with a trailing /),
+// a file is saved into dest if it's a directory, else as dest
+fn tar_get(remote: &dyn Remote, src: &str, dest: &Path, escalate: Option<&Become>) -> Result<()> {
+    let s = quote(src);
+    let is_dir = remote
+        .command(&sh(escalate, format!("test -d {}", s))?)?
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?
+        .success();
+    if is_dir {
+        create_dir_all(dest)?;
+        let script = if src.ends_with('/') {
+            format!("tar -C {} -cf - .", s)
+        } else {
+            let it = Path::new(src);
+            format!(
+                "tar -C {} -cf - {}",
+                quote(&parent(it).display().to_string()),
+                quote(&file_name(it)?)
+            )
+        };
+        return pipe(
+            remote.host(),
+            &mut remote.command(&sh(escalate, script)?)?,
+            ShellCommand::new("tar")
+                .arg("-C")
+                .arg(dest)
+                .arg("--no-same-owner")
+                .arg("-xpf")
+                .arg("-"),
+        );
+    }
+    let dest = if dest.is_dir() {
+        dest.join(file_name(Path::new(src))?)
+    } else {
+        dest.to_path_buf()
+    };
+    pipe(
+        remote.host(),
+        &mut remote.command(&sh(escalate, format!("cat {}", s))?)?,
+        ShellCommand::new("sh")
+            .arg("-c")
+            .arg("cat > \"$1\"")
+            .arg("sh")
+            .arg(dest),
+    )
+}
+
+fn parent(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(it) if !it.as_os_str().is_empty() => it.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+fn file_name(path: &Path) -> Result<String> {
+    path.file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .ok_or_else(|| Error::Custom(format!("bad file name {}", path.display())))
+}
+
+// what a mock transport was asked to do, the script is its rendered content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    Exec {
+        host: String,
+        script: String,
+        escalate: Option<Become>,
+    },
+    Put {
+        host: String,
+        src: PathBuf,
+        dest: String,
+        escalate: Option<Become>,
+        permission: Permission,
+    },
+    Get {
+        host: String,
+        src: String,
+        dest: PathBuf,
+        escalate: Option<Become>,
+    },
+}
+
+impl Operation {
+    pub fn host(&self) -> &str {
+        match self {
+            Self::Exec { host, .. } | Self::Put { host, .. } | Self::Get { host, .. } => host,
+        }
+    }
+}
+
+type Script = dyn Fn(&Operation) -> Result<()> + Send + Sync;
+
+// records the operations of every host in memory, results are ok unless scripted
+#[derive(Clone, Default)]
+pub struct Recorder {
+    operations: Arc<Mutex<Vec<Operation>>>,
+    script: Option<Arc<Script>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn script<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Operation) -> Result<()> + Send + Sync + 'static,
+    {
+        self.script = Some(Arc::new(f));
+        self
+    }
+
+    // for Job::run, every host gets a mock transport sharing this recorder
+    pub fn opener(&self) -> Opener {
+        let it = self.clone();
+        Arc::new(move |_, host, _| {
+            Ok(Box::new(Mock {
+                host: host.to_string(),
+                recorder: it.clone(),
+            }))
+        })
+    }
+
+    pub fn operations(&self) -> Vec<Operation> {
+        match self.operations.lock() {
+            Ok(it) => it.clone(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // hosts run in parallel, only the operations of one host are in a stable order
+    pub fn host(&self, host: &str) -> Vec<Operation> {
+        self.operations()
+            .into_iter()
+            .filter(|x| x.host() == host)
+            .collect()
+    }
+
+    fn record(&self, it: Operation) -> Result<()> {
+        // the rendered script may hold secrets, so only the paths are logged
+        info!(
+            "{}",
+            sensitive::mask(&match it {
+                Operation::Exec { ref host, .. } => format!("mock exec on {}", host),
+                Operation::Put {
+                    ref host,
+                    ref src,
+                    ref dest,
+                    ..
+                } => format!("mock put {} to {}:{}", src.display(), host, dest),
+                Operation::Get {
+                    ref host,
+                    ref src,
+                    ref dest,
+                    ..
+                } => format!("mock get {}:{} to {}", host, src, dest.display()),
+            })
+        );
+        let result = match self.script {
+            Some(ref f) => f(&it),
+            None => Ok(()),
+        };
+        self.operations
+            .lock()
+            .map_err(|e| Error::Custom(e.to_string()))?
+            .push(it);
+        result
+    }
+}
+
+// touches no host, for trying jobs offline
+pub struct Mock {
+    host: String,
+    recorder: Recorder,
+}
+
+impl Mock {
+    pub fn new(host: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            recorder: Recorder::new(),
+        }
+    }
+}
+
+impl Transport for Mock {
+    fn exec(&self, script: &Path, escalate: Option<&Become>) -> Result<()> {
+        self.recorder.record(Operation::Exec {
+            host: self.host.clone(),
+            script: read_to_string(script)?,
+            escalate: escalate.cloned(),
+        })
+    }
+    fn put(
+        &self,
+        src: &Path,
+        dest: &str,
+        escalate: Option<&Become>,
+        permission: &Permission,
+    ) -> Result<()> {
+        self.recorder.record(Operation::Put {
+            host: self.host.clone(),
+            src: src.to_path_buf(),
+            dest: dest.to_string(),
+            escalate: escalate.cloned(),
+            permission: permission.clone(),
+        })
+    }
+    fn get(&self, src: &str, dest: &Path, escalate: Option<&Become>) -> Result<()> {
+        self.recorder.record(Operation::Get {
+            host: self.host.clone(),
+            src: src.to_string(),
+            dest: dest.to_path_buf(),
+            escalate: escalate.cloned(),
+        })
+    }
+}
+
+// closes the master connections left in the socket directory of a run
+pub fn disconnect(sockets: &Path) {
+    let items = match read_dir(sockets) {
+        Ok(it) => it,
+        Err(_) => return,
+    };
+    for it in items.flatten() {
+        let it = it.path();
+        debug!("close ssh master {}", it.display());
+        if let Err(e) = ShellCommand::new("ssh")
+            .arg("-o")
+            .arg(format!("ControlPath={}", it.display()))
+            .arg("-O")
+            .arg("exit")
+            .arg(LOCALHOST)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+        {
+            warn!("can't close ssh master {}: {}", it.display(), e);
+        }
+    }
+}
+
+// appends the commands to tmp/logs/<host>, their outputs go there too
+// the program and its arguments only, the environment of a command is never printed
+fn line(cmd: &ShellCommand) -> String {
+    let mut items = vec![format!("{:?}", cmd.get_program())];
+    items.extend(cmd.get_args().map(|x| format!("{:?}", x)));
+    items.join(" ")
+}
+
+fn log(host: &str, cmds: &[&ShellCommand]) -> Result<File> {
+    let root = ROOT.join("logs");
+    if !root.exists() {
+        create_dir_all(&root)?;
+    }
+    let outputs = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(root.join(host))?;
+    {
+        let mut wrt = BufWriter::new(&outputs);
+        writeln!(
+            wrt,
+            "{}: {}",
+            Utc::now().naive_local(),
+            sensitive::mask(&cmds.iter().map(|x| line(x)).collect::<Vec<_>>().join(" | "))
+        )?;
+    }
+    Ok(outputs)
+}
+
+// runs the command, its outputs are appended to tmp/logs/<host>
+pub fn shell(host: &str, cmd: &mut ShellCommand) -> Result<()> {
+    let outputs = log(host, &[cmd])?;
+    let errors = outputs.try_clone()?;
+
+    let out = cmd
+        .stdout(Stdio::from(outputs))
+        .stderr(Stdio::from(errors))
+        .spawn()?
+        .wait_with_output()?;
+    if !out.status.success() {
+        return Err(Error::Custom(sensitive::mask(&line(cmd))));
+    }
+    Ok(())
+}
+
+// runs from | to, the outputs of to and the errors of both are appended to tmp/logs/<host>
+fn pipe(host: &str, from: &mut ShellCommand, to: &mut ShellCommand) -> Result<()> {
+    let outputs = log(host, &[from, to])?;
+    let mut first = from
+        .stdout(Stdio::piped())
+        .stderr(Stdio::from(outputs.try_clone()?))
+        .spawn()?;
+    let stdin = first
+        .stdout
+        .take()
+        .ok_or_else(|| Error::Custom("could not capture standard output".to_string()))?;
+    let second = to
+        .stdin(Stdio::from(stdin))
+        .stdout(Stdio::from(outputs.try_clone()?))
+        .stderr(Stdio::from(outputs))
+        .spawn()?
+        .wait()?;
+    let first = first.wait()?;
+    for (cmd, status) in &[(from, first), (to, second)] {
+        if !status.success() {
+            return Err(Error::Custom(sensitive::mask(&line(cmd))));
+        }
+    }
+    Ok(())
+}
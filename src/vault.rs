@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{read, read_to_string, remove_file, write, OpenOptions};
+use std::path::Path;
+use std::process::Command as ShellCommand;
+use std::sync::{Mutex, RwLock};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rand::{thread_rng, RngCore};
+use toml::Value;
+use uuid::Uuid;
+
+use super::{
+    errors::{Error, Result},
+    sensitive,
+};
+
+// $AXIS_VAULT;1.0;<base64 of salt, nonce and ciphertext>
+pub const HEADER: &str = "$AXIS_VAULT;1.0;";
+pub const PASSWORD: &str = "AXIS_VAULT_PASSWORD";
+pub const PASSWORD_FILE: &str = "AXIS_VAULT_PASSWORD_FILE";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+const LINE_LEN: usize = 76;
+
+lazy_static! {
+    static ref PASSWORD_VALUE: RwLock<Option<String>> = RwLock::new(None);
+    static ref KEYS: Mutex<BTreeMap<Vec<u8>, Vec<u8>>> = Mutex::new(BTreeMap::new());
+}
+
+pub fn set_password_file<P: AsRef<Path>>(file: P) -> Result<()> {
+    let it = read_to_string(file)?;
+    let mut password = PASSWORD_VALUE
+        .write()
+        .map_err(|e| Error::Custom(e.to_string()))?;
+    *password = Some(it.trim_end_matches(&['\r', '\n'][..]).to_string());
+    Ok(())
+}
+
+// --vault-password-file, then AXIS_VAULT_PASSWORD and AXIS_VAULT_PASSWORD_FILE
+fn password() -> Result<String> {
+    {
+        let it = PASSWORD_VALUE
+            .read()
+            .map_err(|e| Error::Custom(e.to_string()))?;
+        if let Some(ref it) = *it {
+            return Ok(it.clone());
+        }
+    }
+    if let Ok(it) = env::var(PASSWORD) {
+        return Ok(it);
+    }
+    if let Ok(it) = env::var(PASSWORD_FILE) {
+        set_password_file(it)?;
+        return password();
+    }
+    Err(Error::Custom(format!(
+        "please give a vault password by --vault-password-file, {} or {}",
+        PASSWORD, PASSWORD_FILE
+    )))
+}
+
+// derived keys are cached by salt, argon2 is slow on purpose
+fn key(password: &str, salt: &[u8]) -> Result<Vec<u8>> {
+    let mut cache = KEYS.lock().map_err(|e| Error::Custom(e.to_string()))?;
+    if let Some(it) = cache.get(salt) {
+        return Ok(it.clone());
+    }
+    let mut key = vec![0u8; KEY_LEN];
+    Argon2::default()
+        .hash_password_into(password.as_bytes(), salt, &mut key)
+        .map_err(|e| Error::Custom(format!("derive vault key: {}", e)))?;
+    cache.insert(salt.to_vec(), key.clone());
+    Ok(key)
+}
+
+pub fn is_encrypted(buf: &[u8]) -> bool {
+    buf.starts_with(HEADER.as_bytes())
+}
+
+pub fn encrypt(plain: &[u8]) -> Result<String> {
+    let password = password()?;
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    {
+        let mut rng = thread_rng();
+        rng.fill_bytes(&mut salt);
+        rng.fill_bytes(&mut nonce);
+    }
+    let key = key(&password, &salt)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let buf = cipher
+        .encrypt(Nonce::from_slice(&nonce), plain)
+        .map_err(|e| Error::Custom(format!("encrypt vault: {}", e)))?;
+
+    let mut it = Vec::with_capacity(SALT_LEN + NONCE_LEN + buf.len());
+    it.extend_from_slice(&salt);
+    it.extend_from_slice(&nonce);
+    it.extend_from_slice(&buf);
+    Ok(format!("{}{}", HEADER, base64::encode(&it)))
+}
+
+// whitespaces are ignored, so a vault file could be wrapped into lines
+pub fn decrypt(text: &str) -> Result<Vec<u8>> {
+    let text = text
+        .trim()
+        .strip_prefix(HEADER)
+        .ok_or_else(|| Error::Custom("not a vault".to_string()))?;
+    let buf = base64::decode(
+        text.chars()
+            .filter(|x| !x.is_whitespace())
+            .collect::<String>(),
+    )?;
+    if buf.len() < SALT_LEN + NONCE_LEN {
+        return Err(Error::Custom("bad vault".to_string()));
+    }
+    let (salt, buf) = buf.split_at(SALT_LEN);
+    let (nonce, buf) = buf.split_at(NONCE_LEN);
+    let key = key(&password()?, salt)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), buf)
+        .map_err(|_| Error::Custom("can't decrypt vault, wrong password?".to_string()))
+}
+
+pub fn decrypt_str(text: &str) -> Result<String> {
+    Ok(String::from_utf8(decrypt(text)?).map_err(|e| e.utf8_error())?)
+}
+
+// decrypts the encrypted string values in place, they are sensitive by nature
+pub fn reveal(value: &mut Value) -> Result<()> {
+    match value {
+        Value::String(it) if is_encrypted(it.as_bytes()) => {
+            *it = decrypt_str(it)?;
+            sensitive::add(vec![it.clone()]);
+        }
+        Value::Array(it) => {
+            for x in it.iter_mut() {
+                reveal(x)?;
+            }
+        }
+        Value::Table(it) => {
+            for (_, x) in it.iter_mut() {
+                reveal(x)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn wrap(text: &str) -> String {
+    let mut buf = String::new();
+    let mut it = text;
+    while it.len() > LINE_LEN {
+        let (line, rest) = it.split_at(LINE_LEN);
+        buf.push_str(line);
+        buf.push('\n');
+        it = rest;
+    }
+    buf.push_str(it);
+    buf.push('\n');
+    buf
+}
+
+pub fn encrypt_file<P: AsRef<Path>>(file: P) -> Result<()> {
+    let file = file.as_ref();
+    let buf = read(file)?;
+    if is_encrypted(&buf) {
+        return Err(Error::Custom(format!(
+            "{} is encrypted already",
+            file.display()
+        )));
+    }
+    info!("encrypt {}", file.display());
+    write(file, wrap(&encrypt(&buf)?))?;
+    Ok(())
+}
+
+pub fn decrypt_file<P: AsRef<Path>>(file: P) -> Result<()> {
+    let file = file.as_ref();
+    info!("decrypt {}", file.display());
+    let buf = decrypt(&read_to_string(file)?)?;
+    write(file, buf)?;
+    Ok(())
+}
+
+// the plain text is only kept in a private temporary file while editing
+pub fn edit_file<P: AsRef<Path>>(file: P) -> Result<()> {
+    let file = file.as_ref();
+    let buf = if file.exists() {
+        decrypt(&read_to_string(file)?)?
+    } else {
+        Vec::new()
+    };
+    let tmp = env::temp_dir().join(format!(
+        "{}{}",
+        Uuid::new_v4(),
+        file.extension()
+            .and_then(|x| x.to_str())
+            .map(|x| format!(".{}", x))
+            .unwrap_or_default()
+    ));
+    {
+        #[cfg(windows)]
+        let mut tmp = OpenOptions::new().create_new(true).write(true).open(&tmp)?;
+        #[cfg(not(windows))]
+        let mut tmp = {
+            use std::os::unix::fs::OpenOptionsExt;
+            OpenOptions::new()
+                .mode(0o600)
+                .create_new(true)
+                .write(true)
+                .open(&tmp)?
+        };
+        std::io::Write::write_all(&mut tmp, &buf)?;
+    }
+    let editor = env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+    let status = ShellCommand::new(&editor).arg(&tmp).status();
+    let buf = read(&tmp);
+    remove_file(&tmp)?;
+    if !status?.success() {
+        return Err(Error::Custom(format!("{} failed", editor)));
+    }
+    write(file, wrap(&encrypt(&buf?)?))?;
+    Ok(())
+}
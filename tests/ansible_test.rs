@@ -0,0 +1,50 @@
+use std::env::temp_dir;
+use std::path::Path;
+
+use axis::{
+    ansible,
+    models::{Group, Vars},
+};
+use toml::Value;
+use uuid::Uuid;
+
+fn import(src: &str) {
+    let dest = temp_dir().join(Uuid::new_v4().to_string());
+    ansible::import(Path::new(src), &dest).unwrap();
+    let dest = dest.display().to_string();
+
+    let hosts = Group::load("prod", &dest, Vars::new()).unwrap();
+    for (host, vars) in hosts.iter() {
+        println!("{}: {:?}", host, vars);
+    }
+    assert_eq!(hosts.len(), 4);
+    let find = |name: &str| hosts.iter().find(|(x, _)| x == name).unwrap();
+    let (_, vars) = find("web01.example.com");
+    assert_eq!(vars.get("ssh.port"), Some(&Value::Integer(2222)));
+    assert_eq!(
+        vars.get("ssh.user"),
+        Some(&Value::String("root".to_string()))
+    );
+    let (_, vars) = find("db.example.com");
+    assert_eq!(
+        vars.get("ssh.user"),
+        Some(&Value::String("deploy".to_string()))
+    );
+
+    let hosts = Group::load(ansible::UNGROUPED, &dest, Vars::new()).unwrap();
+    assert_eq!(hosts[0].0, "jump.example.com");
+    assert_eq!(
+        hosts[0].1.get("ssh.host"),
+        Some(&Value::String("10.0.0.1".to_string()))
+    );
+}
+
+#[test]
+fn ini() {
+    import("tests/fixtures/ansible.ini");
+}
+
+#[test]
+fn yaml() {
+    import("tests/fixtures/ansible.yaml");
+}
@@ -1,4 +1,4 @@
-use axis::models::Vars;
+use axis::models::{self, Vars};
 use toml::Value;
 
 #[test]
@@ -10,3 +10,164 @@ fn generate() {
 
     println!("{}", toml::to_string_pretty(&vars).unwrap())
 }
+
+#[test]
+fn merge() {
+    let vars: Vars = toml::from_str(
+        r#"
+[nginx]
+port = 80
+root = "/var/www"
+[nginx.ssl]
+enable = false
+
+[redis]
+port = 6379
+host = "localhost"
+"#,
+    )
+    .unwrap();
+    let host: Vars = toml::from_str(
+        r#"
+[nginx.ssl]
+enable = true
+
+[redis]
+__replace__ = true
+port = 6380
+"#,
+    )
+    .unwrap();
+    let vars = models::flatten(&[("group".to_string(), vars), ("host".to_string(), host)]);
+    println!("{}", toml::to_string_pretty(&vars).unwrap());
+
+    let expect: Vars = toml::from_str(
+        r#"
+[nginx]
+port = 80
+root = "/var/www"
+[nginx.ssl]
+enable = true
+
+[redis]
+port = 6380
+"#,
+    )
+    .unwrap();
+    assert_eq!(vars, expect);
+}
+
+#[test]
+fn resolve() {
+    let mut vars: Vars = toml::from_str(
+        r#"
+"job.name" = "ping"
+hostname = "192.168.8.21"
+"app.log" = "{{[app.dir]}}/logs"
+"app.dir" = "/opt/{{job.name}}/{{hostname}}"
+"db.pass" = "p&ss'w=rd<\">"
+"db.url" = "postgres://app:{{[db.pass]}}@db/app"
+[nginx]
+root = "{{app.dir}}/public"
+"#,
+    )
+    .unwrap();
+    models::resolve(&mut vars).unwrap();
+    println!("{}", toml::to_string_pretty(&vars).unwrap());
+    assert_eq!(
+        vars.get("app.log"),
+        Some(&Value::String("/opt/ping/192.168.8.21/logs".to_string()))
+    );
+    assert_eq!(
+        vars.get("nginx").and_then(|x| x.get("root")),
+        Some(&Value::String("/opt/ping/192.168.8.21/public".to_string()))
+    );
+    assert_eq!(
+        vars.get("db.url"),
+        Some(&Value::String(
+            "postgres://app:p&ss'w=rd<\">@db/app".to_string()
+        ))
+    );
+
+    let mut vars: Vars = toml::from_str(
+        r#"
+a = "{{b}}"
+b = "{{c}}"
+c = "{{a}}"
+"#,
+    )
+    .unwrap();
+    assert!(models::resolve(&mut vars).is_err());
+}
+
+#[test]
+fn sensitive() {
+    let vars: Vars = toml::from_str(
+        r#"
+sensitive = ["api-key"]
+"db.password" = "Hi, password!"
+api-key = "Hi, api key!"
+hello = "Hi, world!"
+[nginx]
+ssl-secret = "Hi, ssl!"
+"#,
+    )
+    .unwrap();
+    let redacted = axis::sensitive::redact(&vars);
+    println!("{:?}", redacted);
+    let masked = Value::String(axis::sensitive::MASK.to_string());
+    assert_eq!(redacted.get("db.password"), Some(&masked));
+    assert_eq!(redacted.get("api-key"), Some(&masked));
+    assert_eq!(redacted.get("hello"), vars.get("hello"));
+    assert_eq!(
+        redacted.get("nginx").and_then(|x| x.get("ssl-secret")),
+        Some(&masked)
+    );
+
+    axis::sensitive::register(&vars, &vars);
+    assert_eq!(
+        axis::sensitive::mask("mysql -p 'Hi, password!' -e 'Hi, world!'"),
+        "mysql -p '******' -e 'Hi, world!'"
+    );
+}
+
+#[test]
+fn sources() {
+    let layer = |name: &str, src: &str| (name.to_string(), toml::from_str::<Vars>(src).unwrap());
+    let layers = vec![
+        layer(
+            "group web",
+            r#"
+[nginx]
+port = 80
+root = "/var/www"
+[redis]
+port = 6379
+host = "localhost"
+"#,
+        ),
+        layer(
+            "host web1",
+            r#"
+[nginx]
+port = 8080
+[redis]
+__replace__ = true
+port = 6380
+"#,
+        ),
+    ];
+    let items = models::sources(&layers);
+    let path = |x: &str| x.split('.').map(|x| x.to_string()).collect::<Vec<_>>();
+    let names = |x: &str| {
+        items[&path(x)]
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(items.len(), 3);
+    assert_eq!(names("nginx.port"), vec!["group web", "host web1"]);
+    assert_eq!(names("nginx.root"), vec!["group web"]);
+    assert_eq!(names("redis.port"), vec!["host web1"]);
+    assert!(!items.contains_key(&path("redis.host")));
+}
@@ -1,4 +1,4 @@
-use axis::models::{Group, Vars};
+use axis::models::{expand, Group, Vars};
 use toml::Value;
 
 #[test]
@@ -16,8 +16,175 @@ fn generate() {
                 "host 2".to_string(),
                 "host 3".to_string(),
             ],
+            children: vec!["child 1".to_string(), "child 2".to_string()],
             vars
         })
         .unwrap()
     )
 }
+
+#[test]
+fn children() {
+    let mut vars = Vars::new();
+    vars.insert("key 1".to_string(), Value::String("val 1".to_string()));
+    let hosts = Group::load("cluster", "demo/staging", vars).unwrap();
+    let names: Vec<&str> = hosts.iter().map(|(it, _)| it.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "192.168.8.21",
+            "192.168.8.22",
+            "192.168.8.23",
+            "192.168.8.24",
+            "localhost"
+        ]
+    );
+    for (host, vars) in hosts.iter() {
+        println!("{}: {:?}", host, vars);
+        assert_eq!(vars.get("key 1"), Some(&Value::String("val 1".to_string())));
+    }
+    assert_eq!(
+        hosts[0].1.get("group.name"),
+        Some(&Value::String("nano-pi".to_string()))
+    );
+    assert_eq!(
+        hosts[0].1.get("hello"),
+        Some(&Value::String("Hi, 21!".to_string()))
+    );
+}
+
+#[test]
+fn executable() {
+    let hosts = Group::load("cmdb", "demo/staging", Vars::new()).unwrap();
+    assert_eq!(hosts.len(), 1);
+    let (host, vars) = &hosts[0];
+    println!("{}: {:?}", host, vars);
+    assert_eq!(host, "192.168.8.29");
+    assert_eq!(
+        vars.get("ssh.user"),
+        Some(&Value::String("deploy".to_string()))
+    );
+    assert_eq!(
+        vars.get("hello"),
+        Some(&Value::String("Hi, 29!".to_string()))
+    );
+}
+
+#[test]
+fn single_file() {
+    assert_eq!(
+        Group::names("demo/testing.toml").unwrap(),
+        vec!["local".to_string(), "nano-pi".to_string()]
+    );
+    let hosts = Group::load("nano-pi", "demo/testing.toml", Vars::new()).unwrap();
+    assert_eq!(hosts.len(), 2);
+    for (host, vars) in hosts.iter() {
+        println!("{}: {:?}", host, vars);
+    }
+    assert_eq!(
+        hosts[1].1.get("hello"),
+        Some(&Value::String("Hi, 22!".to_string()))
+    );
+}
+
+#[test]
+fn formats() {
+    let hosts = Group::load("x86", "demo/staging", Vars::new()).unwrap();
+    let (host, vars) = &hosts[0];
+    println!("{}: {:?}", host, vars);
+    assert_eq!(vars.get("ssh.port"), Some(&Value::Integer(2222)));
+    assert_eq!(
+        vars.get("hello"),
+        Some(&Value::String("Hi, 30!".to_string()))
+    );
+}
+
+#[test]
+fn directories() {
+    let hosts = Group::load("local", "demo/staging", Vars::new()).unwrap();
+    let (host, vars) = &hosts[0];
+    println!("{}: {:?}", host, vars);
+    assert_eq!(
+        vars.get("ssh.user"),
+        Some(&Value::String("deploy".to_string()))
+    );
+    assert_eq!(
+        vars.get("hello"),
+        Some(&Value::String("Hi, localhost!".to_string()))
+    );
+}
+
+#[test]
+fn trace() {
+    let hosts = Group::trace(
+        "cluster",
+        "demo/staging",
+        vec![("task 1".to_string(), Vars::new())],
+    )
+    .unwrap();
+    let (host, layers) = &hosts[0];
+    assert_eq!(host, "192.168.8.21");
+    assert_eq!(
+        layers.iter().map(|(x, _)| x.as_str()).collect::<Vec<_>>(),
+        vec![
+            "task 1",
+            "group cluster",
+            "built-in",
+            "group nano-pi",
+            "built-in",
+            "host 192.168.8.21",
+            "built-in"
+        ]
+    );
+}
+
+#[test]
+fn cycle() {
+    let err = Group::load("a", "tests/fixtures/cycle", Vars::new()).unwrap_err();
+    assert_eq!(err.to_string(), "cycle detected in groups: a -> b -> a");
+}
+
+// the host file replaces the table of the group, the other file of the host is merged into it
+#[test]
+fn replace() {
+    let hosts = Group::load("web", "tests/fixtures/replace", Vars::new()).unwrap();
+    let expect: Value = toml::from_str(
+        r#"
+port = 8080
+access_log = "off"
+"#,
+    )
+    .unwrap();
+    assert_eq!(hosts[0].1.get("nginx"), Some(&expect));
+}
+
+#[test]
+fn ranges() {
+    let names = expand("node[01:24].lab").unwrap();
+    assert_eq!(names.len(), 24);
+    assert_eq!(names[0], "node01.lab");
+    assert_eq!(names[23], "node24.lab");
+    assert_eq!(
+        expand("node[1:8:2]").unwrap(),
+        vec!["node1", "node3", "node5", "node7"]
+    );
+    assert_eq!(
+        expand("rack-[a:c]").unwrap(),
+        vec!["rack-a", "rack-b", "rack-c"]
+    );
+    assert_eq!(
+        expand("rack-[a:b][1:3:2]").unwrap(),
+        vec!["rack-a1", "rack-a3", "rack-b1", "rack-b3"]
+    );
+    assert_eq!(expand("localhost").unwrap(), vec!["localhost"]);
+    for it in &[
+        "node[5:1]",
+        "node[1:8:0]",
+        "node[1]",
+        "node[1:x]",
+        "node[a:10]",
+        "node[1:2:3:4]",
+    ] {
+        assert!(expand(it).is_err(), "{}", it);
+    }
+}
@@ -1,4 +1,4 @@
-use axis::models::{Command, Job, Task, Vars};
+use axis::models::{Command, Job, Method, Permission, Privilege, Task, Vars};
 use toml::Value;
 
 #[test]
@@ -40,13 +40,26 @@ fn generate() {
         Command::Upload {
             src: "tmp/uuu".to_string(),
             dest: "/etc/uuu".to_string(),
+            privilege: Privilege::default(),
+            permission: Permission {
+                owner: Some("www-data".to_string()),
+                group: None,
+                mode: Some("0640".to_string()),
+                dir_mode: Some("0750".to_string()),
+            },
         },
         Command::Download {
             src: "/etc/ddd".to_string(),
             dest: "tmp/ddd".to_string(),
+            privilege: Privilege::default(),
         },
         Command::Shell {
             script: "aaa.sh".to_string(),
+            privilege: Privilege {
+                enabled: Some(true),
+                user: Some("deploy".to_string()),
+                method: Some(Method::Sudo),
+            },
         },
     ];
     println!(
@@ -57,19 +70,22 @@ fn generate() {
                     name: "task 1".to_string(),
                     groups: groups.clone(),
                     commands: commands.clone(),
-                    vars: tvr.clone()
+                    vars: tvr.clone(),
+                    privilege: Privilege::default(),
                 },
                 Task {
                     name: "task 2".to_string(),
                     groups: groups.clone(),
                     commands: commands.clone(),
-                    vars: tvr.clone()
+                    vars: tvr.clone(),
+                    privilege: Privilege::default(),
                 },
                 Task {
                     name: "task 3".to_string(),
                     groups: groups.clone(),
                     commands: commands.clone(),
-                    vars: tvr.clone()
+                    vars: tvr.clone(),
+                    privilege: Privilege::default(),
                 }
             ],
             vars
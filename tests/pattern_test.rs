@@ -0,0 +1,37 @@
+use axis::{models::Vars, pattern};
+
+fn hosts(pattern: &str) -> Vec<String> {
+    pattern::select(pattern, "demo/staging", Vars::new())
+        .unwrap()
+        .into_iter()
+        .map(|(it, _)| it)
+        .collect()
+}
+
+#[test]
+fn select() {
+    assert_eq!(hosts("local"), vec!["localhost"]);
+    assert_eq!(
+        hosts("nano-pi:!192.168.8.21,!192.168.8.22"),
+        vec!["192.168.8.23", "192.168.8.24"]
+    );
+    assert_eq!(
+        hosts("cluster:&nano-pi:!~2[34]$"),
+        vec!["192.168.8.21", "192.168.8.22"]
+    );
+    assert_eq!(hosts("192.168.8.2?:&local"), Vec::<String>::new());
+    assert_eq!(hosts("nano*:local"), hosts("cluster"));
+    assert!(pattern::select("unknown", "demo/staging", Vars::new()).is_err());
+}
+
+#[test]
+fn regex() {
+    assert_eq!(
+        hosts("local,~^192\\.168\\.8\\.2[1-2]{1,2}$"),
+        vec!["localhost", "192.168.8.21", "192.168.8.22"]
+    );
+    assert_eq!(
+        hosts("cluster:!~^(192.168.8.21|192.168.8.2[34]|local.*)$"),
+        vec!["192.168.8.22"]
+    );
+}
@@ -0,0 +1,105 @@
+use std::env::set_current_dir;
+use std::fs::{create_dir_all, metadata, read_to_string, set_permissions, write, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use axis::{
+    cache::Cache,
+    errors::Error,
+    models::{Become, Job, Method},
+    transport::{Operation, Recorder},
+};
+
+// one test only, the render cache and the working directory are process wide
+#[test]
+fn record() {
+    set_current_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("demo")).unwrap();
+    let cache = Cache::new(false).unwrap();
+
+    let recorder = Recorder::new();
+    Job::run(
+        "ping",
+        "staging",
+        Job::load("ping", "staging").unwrap(),
+        recorder.opener(),
+    )
+    .unwrap();
+    assert_eq!(recorder.operations().len(), 5 * 4);
+    for (host, hello) in &[("192.168.8.21", "Hi, 21!"), ("localhost", "Hi, localhost!")] {
+        let items = recorder.host(host);
+        println!("{:?}", items);
+        assert_eq!(items.len(), 4);
+        assert!(matches!(items[0], Operation::Put { ref dest, .. } if dest == "/tmp/hi.sh"));
+        match items[1] {
+            Operation::Put {
+                ref src,
+                ref dest,
+                ref permission,
+                ..
+            } => {
+                assert_eq!(dest, "/tmp/hi");
+                assert_eq!(permission.mode.as_deref(), Some("0644"));
+                assert_eq!(permission.dir_mode.as_deref(), Some("0755"));
+                assert!(src.ends_with("etc"));
+                assert_eq!(
+                    metadata(src).unwrap().permissions().mode(),
+                    metadata("jobs/etc").unwrap().permissions().mode()
+                );
+                assert_eq!(
+                    read_to_string(src.join("app.toml")).unwrap(),
+                    format!("greeting = \"{}\"\njob = \"ping\"\n", hello)
+                );
+                assert_eq!(
+                    read_to_string(src.join("conf.d/log.toml")).unwrap(),
+                    "level = \"info\"\n"
+                );
+            }
+            ref v => panic!("unexpected {:?}", v),
+        }
+        assert_eq!(
+            items[2],
+            Operation::Get {
+                host: host.to_string(),
+                src: "/proc/version".to_string(),
+                dest: Path::new("tmp")
+                    .join("downloads")
+                    .join(host)
+                    .join("proc/version"),
+                escalate: None,
+            }
+        );
+        assert!(
+            matches!(items[3], Operation::Exec { ref script, .. } if script.contains(&format!("echo {}", hello)))
+        );
+        assert!(
+            matches!(items[3], Operation::Exec { escalate: Some(Become { ref user, method: Method::Sudo }), .. } if user == "root")
+        );
+    }
+
+    // nano-pi goes first, a failed host stops the job before local
+    let recorder = Recorder::new().script(|it| match it {
+        Operation::Get { host, .. } if host == "192.168.8.22" => {
+            Err(Error::Custom("no route to host".to_string()))
+        }
+        _ => Ok(()),
+    });
+    let err = Job::run(
+        "ping",
+        "staging",
+        Job::load("ping", "staging").unwrap(),
+        recorder.opener(),
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "no route to host");
+    assert_eq!(recorder.host("192.168.8.22").len(), 3);
+    assert_eq!(recorder.host("192.168.8.21").len(), 4);
+    assert!(recorder.host("localhost").is_empty());
+
+    // a read-only staged directory is removed too
+    let root = Cache::root().unwrap();
+    create_dir_all(root.join("ro")).unwrap();
+    write(root.join("ro/secret"), "Hi!").unwrap();
+    set_permissions(root.join("ro"), Permissions::from_mode(0o555)).unwrap();
+    drop(cache);
+    assert!(!root.exists());
+}
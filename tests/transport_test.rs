@@ -0,0 +1,102 @@
+use std::env::{set_current_dir, temp_dir};
+use std::fs::{create_dir_all, metadata, read_to_string, remove_dir_all, write};
+use std::os::unix::fs::PermissionsExt;
+
+use axis::{
+    models::{Permission, Vars},
+    transport::{Local, Ssh, Transport},
+};
+use toml::Value;
+use uuid::Uuid;
+
+// rsync or tar, whichever the machine has, the results must be the same
+#[test]
+fn local() {
+    let root = temp_dir().join(Uuid::new_v4().to_string());
+    create_dir_all(root.join("src/etc")).unwrap();
+    set_current_dir(&root).unwrap();
+    write(root.join("src/etc/app.conf"), "port = 8080\n").unwrap();
+    write(root.join("src/hi.sh"), "echo hi\n").unwrap();
+
+    let it = Local::new("localhost", &Vars::new());
+    let dest = root.join("dest");
+    create_dir_all(&dest).unwrap();
+    it.put(
+        &root.join("src/etc"),
+        &dest.display().to_string(),
+        None,
+        &Permission {
+            mode: Some("0640".to_string()),
+            dir_mode: Some("0750".to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    it.put(
+        &root.join("src/hi.sh"),
+        &dest.join("hi").display().to_string(),
+        None,
+        &Permission::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        read_to_string(dest.join("etc/app.conf")).unwrap(),
+        "port = 8080\n"
+    );
+    assert_eq!(read_to_string(dest.join("hi")).unwrap(), "echo hi\n");
+    let mode = |x: &str| metadata(dest.join(x)).unwrap().permissions().mode() & 0o7777;
+    assert_eq!(mode("etc"), 0o750);
+    assert_eq!(mode("etc/app.conf"), 0o640);
+
+    let back = root.join("back");
+    it.get(&dest.join("etc").display().to_string(), &back, None)
+        .unwrap();
+    it.get(&dest.join("hi").display().to_string(), &back, None)
+        .unwrap();
+    assert_eq!(
+        read_to_string(back.join("etc/app.conf")).unwrap(),
+        "port = 8080\n"
+    );
+    assert_eq!(read_to_string(back.join("hi")).unwrap(), "echo hi\n");
+
+    remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn jump() {
+    let mut vars = Vars::new();
+    vars.insert("connection".to_string(), Value::String("ssh".to_string()));
+    vars.insert("ssh.port".to_string(), Value::Integer(2200));
+    vars.insert(
+        "ssh.key-file".to_string(),
+        Value::String("/keys/id_ed25519".to_string()),
+    );
+    vars.insert(
+        "ssh.jump".to_string(),
+        Value::Array(vec![
+            Value::String("ops@bastion:2222".to_string()),
+            Value::String("10.0.0.1".to_string()),
+        ]),
+    );
+    // absolute, the local test changes the working directory
+    let inventory = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/jump");
+    let it = Ssh::new(inventory, "10.0.1.5", &vars).unwrap();
+    let options = it.options();
+    println!("{:?}", options);
+    let common = format!(
+        "-o ConnectTimeout=3 -o ConnectionAttempts=5 \
+         -o PasswordAuthentication=no -o IdentitiesOnly=yes -i /keys/id_ed25519 \
+         -o StrictHostKeyChecking=accept-new -o UserKnownHostsFile={}/known_hosts",
+        inventory
+    );
+    assert_eq!(&options[..3], &["-T", "-p", "2200"]);
+    assert_eq!(options[3..options.len() - 2].join(" "), common);
+    assert_eq!(options[options.len() - 2], "-o");
+    assert_eq!(
+        options[options.len() - 1],
+        format!(
+            "ProxyCommand=ssh -p 22 {} -o 'ProxyCommand=ssh -p 2222 {} -l ops bastion -W %%h:%%p' 10.0.0.1 -W %h:%p",
+            common, common
+        )
+    );
+}
@@ -0,0 +1,47 @@
+use std::env::temp_dir;
+use std::fs::{create_dir_all, write};
+
+use axis::{
+    models::{Group, Vars},
+    sensitive, vault,
+};
+use toml::Value;
+use uuid::Uuid;
+
+#[test]
+fn inventory() {
+    let root = temp_dir().join(Uuid::new_v4().to_string());
+    create_dir_all(root.join("groups")).unwrap();
+    create_dir_all(root.join("hosts")).unwrap();
+    write(root.join("password"), "change-me\n").unwrap();
+    vault::set_password_file(root.join("password")).unwrap();
+
+    let token = vault::encrypt(b"Hi, token!").unwrap();
+    println!("{}", token);
+    write(
+        root.join("groups").join("db.toml"),
+        format!("hosts = [\"db1\"]\n[vars]\ntoken = {:?}\n", token),
+    )
+    .unwrap();
+    let host = root.join("hosts").join("db1.toml");
+    write(
+        &host,
+        "password = \"Hi, password!\"\nmotd = \"Hi, vault!\"\n",
+    )
+    .unwrap();
+    vault::encrypt_file(&host).unwrap();
+    assert!(vault::encrypt_file(&host).is_err());
+
+    let hosts = Group::load("db", &root.display().to_string(), Vars::new()).unwrap();
+    let (_, vars) = &hosts[0];
+    assert_eq!(
+        vars.get("token"),
+        Some(&Value::String("Hi, token!".to_string()))
+    );
+    assert_eq!(
+        vars.get("password"),
+        Some(&Value::String("Hi, password!".to_string()))
+    );
+    // every value of an encrypted file is a secret, whatever its key is
+    assert_eq!(sensitive::mask("echo Hi, vault!"), "echo ******");
+}